@@ -0,0 +1,466 @@
+use crate::config::{Config, DEFAULT_EMBEDDING_MODEL};
+use crate::db::Database;
+use crate::search::{self, SearchRecord};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{BufRead, Read, Write};
+use std::path::Path;
+
+/// Bumped whenever the bundle layout changes in a way older readers can't handle.
+pub const BUNDLE_FORMAT_VERSION: u32 = 6;
+/// Bundles older than this carry vectors keyed by snapshot, which the content-keyed index can't use.
+const KEYED_VECTORS_VERSION: u32 = 4;
+/// The archive entry holding everything but the objects.
+const MANIFEST_ENTRY: &str = "manifest.json";
+/// Archive directory holding each object's raw bytes, named by hash.
+const OBJECTS_DIR: &str = "objects/";
+
+/// A bundle is a gzipped tar archive: a JSON manifest followed by the CAS objects as files.
+#[derive(Serialize, Deserialize)]
+pub struct Bundle {
+    pub format_version: u32,
+    pub created_at: i64,
+    pub project_name: String,
+    pub sessions: Vec<SessionRow>,
+    pub snapshots: Vec<SnapshotRow>,
+    /// CAS objects keyed by their BLAKE3 hash. Stored as archive entries, not in the manifest.
+    #[serde(skip)]
+    pub objects: BTreeMap<String, Vec<u8>>,
+    pub vectors: Option<Vec<SearchRecord>>,
+    /// Embedding model the vectors were built with. Absent in bundles from before models were
+    /// configurable, which always used the default.
+    #[serde(default)]
+    pub vector_model: Option<String>,
+    /// Tags on the bundled snapshots. Absent before format v5.
+    #[serde(default)]
+    pub tags: Vec<TagRow>,
+    /// Notes on the bundled snapshots and sessions. Absent before format v5.
+    #[serde(default)]
+    pub notes: Vec<NoteRow>,
+}
+
+/// Bundles before format v6 were a single JSON document with the objects inlined as text.
+#[derive(Deserialize)]
+struct JsonBundle {
+    #[serde(flatten)]
+    bundle: Bundle,
+    objects: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TagRow {
+    pub name: String,
+    pub timestamp: i64,
+    pub all_files: bool,
+    pub created_at: i64,
+    /// Only the tagged snapshots that are in the bundle.
+    pub snapshot_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct NoteRow {
+    pub id: String,
+    pub target_kind: String,
+    pub target_id: String,
+    pub body: String,
+    pub created_at: i64,
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct SessionRow {
+    pub id: String,
+    pub start_time: i64,
+    pub end_time: Option<i64>,
+    pub meta: Option<String>,
+    pub label: Option<String>,
+    pub branch: Option<String>,
+    pub trigger_kind: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct SnapshotRow {
+    pub id: String,
+    pub session_id: String,
+    pub file_path: String,
+    pub timestamp: i64,
+    pub diff_patch: String,
+    pub content_hash: String,
+    pub lines_added: i32,
+    pub lines_removed: i32,
+}
+
+#[derive(Default)]
+pub struct BundleFilter {
+    pub path_glob: Option<String>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub include_vectors: bool,
+}
+
+#[derive(Default)]
+pub struct ImportStats {
+    pub sessions: u64,
+    pub snapshots: u64,
+    pub objects: u64,
+    pub vectors: u64,
+    /// Chunks indexed for keywords only, awaiting `stasher reindex` for vectors.
+    pub keyword_only: u64,
+    pub tags: u64,
+    /// Tags left out because this project already has a tag by that name.
+    pub tag_conflicts: Vec<String>,
+    pub notes: u64,
+}
+
+pub async fn create_bundle(db: &Database, base_path: &Path, filter: &BundleFilter) -> Result<Bundle> {
+    let matcher = match &filter.path_glob {
+        Some(glob) => Some(
+            globset::Glob::new(glob)
+                .context(format!("Invalid path glob: {}", glob))?
+                .compile_matcher(),
+        ),
+        None => None,
+    };
+
+    let snapshots: Vec<SnapshotRow> = sqlx::query_as(
+        "SELECT id, session_id, file_path, timestamp, diff_patch, content_hash, lines_added, lines_removed
+         FROM snapshots WHERE timestamp >= ? AND timestamp <= ? ORDER BY timestamp ASC"
+    )
+    .bind(filter.since.unwrap_or(i64::MIN))
+    .bind(filter.until.unwrap_or(i64::MAX))
+    .fetch_all(&db.sqlite)
+    .await?
+    .into_iter()
+    .filter(|s: &SnapshotRow| matcher.as_ref().is_none_or(|m| m.is_match(&s.file_path)))
+    .collect();
+
+    let session_ids: HashSet<&str> = snapshots.iter().map(|s| s.session_id.as_str()).collect();
+    let sessions: Vec<SessionRow> = sqlx::query_as("SELECT id, start_time, end_time, meta, label, branch, trigger_kind FROM sessions ORDER BY start_time ASC")
+        .fetch_all(&db.sqlite)
+        .await?
+        .into_iter()
+        .filter(|s: &SessionRow| session_ids.contains(s.id.as_str()))
+        .collect();
+
+    let objects_path = base_path.join(".stasher").join("objects");
+    let mut objects = BTreeMap::new();
+    for snap in &snapshots {
+        if objects.contains_key(&snap.content_hash) {
+            continue;
+        }
+        let content = fs::read(objects_path.join(&snap.content_hash))
+            .context(format!("Failed to read object {} for {}", snap.content_hash, snap.file_path))?;
+        objects.insert(snap.content_hash.clone(), content);
+    }
+
+    // Lists are bound as JSON so any number of ids fits in one parameter
+    let snapshot_ids = serde_json::to_string(&snapshots.iter().map(|s| &s.id).collect::<Vec<_>>())?;
+    let session_ids = serde_json::to_string(&sessions.iter().map(|s| &s.id).collect::<Vec<_>>())?;
+
+    let tagged: Vec<(String, i64, bool, i64, String)> = sqlx::query_as(
+        "SELECT t.name, t.timestamp, t.all_files, t.created_at, ts.snapshot_id
+         FROM tags t JOIN tag_snapshots ts ON ts.tag_name = t.name
+         WHERE ts.snapshot_id IN (SELECT value FROM json_each(?))
+         ORDER BY t.name"
+    )
+    .bind(&snapshot_ids)
+    .fetch_all(&db.sqlite)
+    .await?;
+    let mut tags: Vec<TagRow> = Vec::new();
+    for (name, timestamp, all_files, created_at, snapshot_id) in tagged {
+        match tags.last_mut() {
+            Some(tag) if tag.name == name => tag.snapshot_ids.push(snapshot_id),
+            _ => tags.push(TagRow { name, timestamp, all_files, created_at, snapshot_ids: vec![snapshot_id] }),
+        }
+    }
+
+    let notes: Vec<NoteRow> = sqlx::query_as(
+        "SELECT id, target_kind, target_id, body, created_at FROM notes
+         WHERE (target_kind = 'snapshot' AND target_id IN (SELECT value FROM json_each(?)))
+            OR (target_kind = 'session' AND target_id IN (SELECT value FROM json_each(?)))
+         ORDER BY created_at ASC"
+    )
+    .bind(&snapshot_ids)
+    .bind(&session_ids)
+    .fetch_all(&db.sqlite)
+    .await?;
+
+    let index = search::active_index(db).await?;
+    let vectors = if filter.include_vectors {
+        // Content chunks are keyed by hash, added/removed chunks by snapshot id
+        let keys: HashSet<String> = snapshots
+            .iter()
+            .flat_map(|s| [s.id.clone(), s.content_hash.clone()])
+            .collect();
+        Some(search::read_records(&db.lancedb, &index.chunks_table(), &keys).await?)
+    } else {
+        None
+    };
+
+    let project_name = base_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    Ok(Bundle {
+        format_version: BUNDLE_FORMAT_VERSION,
+        created_at: Utc::now().timestamp_millis(),
+        project_name,
+        sessions,
+        snapshots,
+        objects,
+        vector_model: vectors.as_ref().and(index.model),
+        vectors,
+        tags,
+        notes,
+    })
+}
+
+pub fn write_bundle(bundle: &Bundle, file: &Path) -> Result<()> {
+    let out = fs::File::create(file)
+        .context(format!("Failed to create bundle file {}", file.display()))?;
+    let mut archive = tar::Builder::new(flate2::write::GzEncoder::new(
+        std::io::BufWriter::new(out),
+        flate2::Compression::default(),
+    ));
+
+    let manifest = serde_json::to_vec(bundle)?;
+    append_entry(&mut archive, MANIFEST_ENTRY, &manifest, bundle.created_at)?;
+    for (hash, content) in &bundle.objects {
+        append_entry(&mut archive, &format!("{}{}", OBJECTS_DIR, hash), content, bundle.created_at)?;
+    }
+
+    archive.into_inner()?.finish()?.flush()
+        .context(format!("Failed to write bundle file {}", file.display()))?;
+    Ok(())
+}
+
+fn append_entry<W: Write>(archive: &mut tar::Builder<W>, name: &str, data: &[u8], created_at: i64) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime((created_at / 1000).max(0) as u64);
+    archive.append_data(&mut header, name, data)
+        .context(format!("Failed to add {} to the bundle", name))?;
+    Ok(())
+}
+
+pub fn read_bundle(file: &Path) -> Result<Bundle> {
+    let mut input = std::io::BufReader::new(
+        fs::File::open(file).context(format!("Failed to open bundle file {}", file.display()))?,
+    );
+    let is_gzip = input.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+
+    let bundle = if is_gzip {
+        read_archive(input)?
+    } else {
+        let legacy: JsonBundle = serde_json::from_reader(input).context("Failed to parse bundle")?;
+        Bundle {
+            objects: legacy.objects.into_iter().map(|(hash, content)| (hash, content.into_bytes())).collect(),
+            ..legacy.bundle
+        }
+    };
+
+    if bundle.format_version > BUNDLE_FORMAT_VERSION {
+        anyhow::bail!(
+            "Bundle format v{} is newer than this version of Stasher supports (v{}). Please upgrade.",
+            bundle.format_version,
+            BUNDLE_FORMAT_VERSION
+        );
+    }
+    Ok(bundle)
+}
+
+fn read_archive(input: impl Read) -> Result<Bundle> {
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(input));
+    let mut manifest: Option<Bundle> = None;
+    let mut objects = BTreeMap::new();
+    for entry in archive.entries().context("Failed to read bundle archive")? {
+        let mut entry = entry.context("Failed to read bundle archive")?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).context(format!("Failed to read {} from the bundle", name))?;
+        if name == MANIFEST_ENTRY {
+            manifest = Some(serde_json::from_slice(&data).context("Failed to parse bundle manifest")?);
+        } else if let Some(hash) = name.strip_prefix(OBJECTS_DIR) {
+            objects.insert(hash.to_string(), data);
+        }
+    }
+    let bundle = manifest.context("Bundle archive has no manifest")?;
+    Ok(Bundle { objects, ..bundle })
+}
+
+/// Merges a bundle into the project's history. Rows that already exist are left untouched,
+/// so importing the same bundle twice is harmless.
+pub async fn import_bundle(db: &Database, base_path: &Path, bundle: Bundle) -> Result<ImportStats> {
+    let mut stats = ImportStats::default();
+
+    // Objects first, so no snapshot ever points at missing content
+    let objects_path = base_path.join(".stasher").join("objects");
+    for (hash, content) in &bundle.objects {
+        if blake3::hash(content).to_hex().as_str() != hash {
+            anyhow::bail!("Bundle object {} is corrupt (hash mismatch)", hash);
+        }
+        let object_path = objects_path.join(hash);
+        if !object_path.exists() {
+            fs::write(object_path, content)?;
+            stats.objects += 1;
+        }
+    }
+
+    let mut tx = db.sqlite.begin().await?;
+    for session in &bundle.sessions {
+        stats.sessions += sqlx::query(
+            "INSERT OR IGNORE INTO sessions (id, start_time, end_time, meta, label, branch, trigger_kind)
+             VALUES (?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(&session.id)
+        .bind(session.start_time)
+        .bind(session.end_time)
+        .bind(&session.meta)
+        .bind(&session.label)
+        .bind(&session.branch)
+        .bind(&session.trigger_kind)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    }
+
+    let mut new_snapshots = HashSet::new();
+    for snap in &bundle.snapshots {
+        let inserted = sqlx::query(
+            "INSERT OR IGNORE INTO snapshots (id, session_id, file_path, timestamp, diff_patch, content_hash, lines_added, lines_removed)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(&snap.id)
+        .bind(&snap.session_id)
+        .bind(&snap.file_path)
+        .bind(snap.timestamp)
+        .bind(&snap.diff_patch)
+        .bind(&snap.content_hash)
+        .bind(snap.lines_added)
+        .bind(snap.lines_removed)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        if inserted > 0 {
+            new_snapshots.insert(snap.id.clone());
+        }
+    }
+
+    for tag in &bundle.tags {
+        let inserted = sqlx::query("INSERT OR IGNORE INTO tags (name, timestamp, all_files, created_at) VALUES (?, ?, ?, ?)")
+            .bind(&tag.name)
+            .bind(tag.timestamp)
+            .bind(tag.all_files)
+            .bind(tag.created_at)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        if inserted == 0 {
+            // Merging into a local tag of the same name would change what it points at
+            stats.tag_conflicts.push(tag.name.clone());
+            continue;
+        }
+        for snapshot_id in &tag.snapshot_ids {
+            sqlx::query("INSERT OR IGNORE INTO tag_snapshots (tag_name, snapshot_id) VALUES (?, ?)")
+                .bind(&tag.name)
+                .bind(snapshot_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        stats.tags += 1;
+    }
+
+    for note in &bundle.notes {
+        let inserted = sqlx::query("INSERT OR IGNORE INTO notes (id, target_kind, target_id, body, created_at) VALUES (?, ?, ?, ?, ?)")
+            .bind(&note.id)
+            .bind(&note.target_kind)
+            .bind(&note.target_id)
+            .bind(&note.body)
+            .bind(note.created_at)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        if inserted > 0 {
+            sqlx::query("INSERT INTO notes_fts (note_id, body) VALUES (?, ?)")
+                .bind(&note.id)
+                .bind(&note.body)
+                .execute(&mut *tx)
+                .await?;
+            stats.notes += 1;
+        }
+    }
+    tx.commit().await?;
+    stats.snapshots = new_snapshots.len() as u64;
+    if stats.notes > 0 {
+        // Notes carry no vectors; `stasher reindex` embeds them
+        search::mark_vectors_incomplete(&db.sqlite).await?;
+    }
+
+    // Vectors are re-inserted through the connection rather than copied as files,
+    // so the store stays valid wherever the project now lives.
+    let mut with_vectors: HashSet<(String, String)> = HashSet::new();
+    if bundle.vectors.is_some() && bundle.format_version < KEYED_VECTORS_VERSION {
+        eprintln!("⚠️ Bundle vectors predate the current index layout and were skipped. Run `stasher reindex` to make the imported snapshots searchable by meaning.");
+    } else if let Some(vectors) = bundle.vectors {
+        let index = search::active_index(db).await?;
+        let target_model = match index.model.clone() {
+            Some(model) => model,
+            None => search::embedder::model_id(&Config::load(base_path)?),
+        };
+        let bundle_model = bundle.vector_model.as_deref().unwrap_or(DEFAULT_EMBEDDING_MODEL);
+        if bundle_model != target_model {
+            eprintln!(
+                "⚠️ Bundle vectors were built with {} but this project uses {}; they were skipped. Run `stasher reindex` to make the imported snapshots searchable by meaning.",
+                bundle_model, target_model
+            );
+        } else {
+            let mut records = Vec::new();
+            for record in vectors {
+                let wanted = if record.kind == "content" {
+                    !search::is_indexed(&db.sqlite, &record.key).await?
+                } else {
+                    new_snapshots.contains(&record.key)
+                };
+                if wanted {
+                    with_vectors.insert((record.key.clone(), record.kind.clone()));
+                    records.push(record);
+                }
+            }
+            stats.vectors = records.len() as u64;
+            let dim = records.first().map_or(0, |r| r.vector.len() as i32);
+            search::write_records(&db.lancedb, &index.chunks_table(), &target_model, dim, records.clone()).await?;
+            search::write_lexical(&db.sqlite, &records).await?;
+        }
+    }
+
+    // Whatever came without usable vectors is still indexed for keyword search
+    for snap in bundle.snapshots.iter().filter(|s| new_snapshots.contains(&s.id)) {
+        let meta = search::SnapshotMeta {
+            snapshot_id: snap.id.clone(),
+            file_path: snap.file_path.clone(),
+            content_hash: snap.content_hash.clone(),
+        };
+        // Only text is chunked; other content is still restorable from the objects
+        let content = match bundle.objects.get(&snap.content_hash).and_then(|c| std::str::from_utf8(c).ok()) {
+            Some(content) if !search::is_indexed(&db.sqlite, &snap.content_hash).await? => Some(content),
+            _ => None,
+        };
+        // A new file's patch is the whole file, already covered by its content
+        let patch = (!snap.diff_patch.starts_with("--- /dev/null")).then_some(snap.diff_patch.as_str());
+        let records: Vec<SearchRecord> = search::snapshot_chunks(&meta, content, patch)
+            .into_iter()
+            .map(|(record, _)| record)
+            .filter(|r| !with_vectors.contains(&(r.key.clone(), r.kind.clone())))
+            .collect();
+        stats.keyword_only += records.len() as u64;
+        search::write_keywords_only(&db.sqlite, &records).await?;
+    }
+
+    Ok(stats)
+}
@@ -0,0 +1,90 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The embedding model used when a project has no configuration.
+pub const DEFAULT_EMBEDDING_MODEL: &str = "nomic-ai/nomic-embed-text-v1.5";
+
+/// Where embeddings come from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingBackend {
+    /// An ONNX model run in-process.
+    #[default]
+    #[serde(rename = "fastembed")]
+    FastEmbed,
+    /// An Ollama-compatible server at `embedding_url`.
+    Http,
+    /// Deterministic word hashing, for tests.
+    Hash,
+}
+
+/// Per-project settings stored in `.stasher/config.json`. Missing keys take their defaults,
+/// so older files keep working as options are added.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub embedding_backend: EmbeddingBackend,
+    /// fastembed model code, e.g. `nomic-ai/nomic-embed-text-v1.5` or `BAAI/bge-small-en-v1.5`,
+    /// or the model name the HTTP server knows, e.g. `nomic-embed-text`.
+    pub embedding_model: String,
+    /// Base URL of the embedding server, e.g. `http://localhost:11434`.
+    pub embedding_url: Option<String>,
+    /// Vector size for the hash backend, or of the HTTP server's model (otherwise learned from its first response).
+    pub embedding_dim: Option<usize>,
+    /// Where installed and downloaded models live. Defaults to `~/.stasher/models`, shared by
+    /// every project on the machine.
+    pub model_cache_dir: Option<PathBuf>,
+    /// Never download models. Without an installed model, search falls back to keywords only.
+    pub offline: bool,
+    /// Chunk count past which the daemon builds an approximate vector index instead of
+    /// searching by brute force.
+    pub vector_index_threshold: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            embedding_backend: EmbeddingBackend::default(),
+            embedding_model: DEFAULT_EMBEDDING_MODEL.to_string(),
+            embedding_url: None,
+            embedding_dim: None,
+            model_cache_dir: None,
+            offline: false,
+            vector_index_threshold: 10_000,
+        }
+    }
+}
+
+impl Config {
+    pub fn path(base_path: &Path) -> PathBuf {
+        base_path.join(".stasher").join("config.json")
+    }
+
+    pub fn load(base_path: &Path) -> Result<Self> {
+        let path = Self::path(base_path);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let raw = fs::read_to_string(&path)
+            .context(format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&raw).context(format!("Invalid config file {}", path.display()))
+    }
+
+    pub fn model_cache_dir(&self) -> Result<PathBuf> {
+        match &self.model_cache_dir {
+            Some(dir) => Ok(dir.clone()),
+            None => {
+                let home_dir = home::home_dir().context("Could not find home directory")?;
+                Ok(home_dir.join(".stasher").join("models"))
+            }
+        }
+    }
+
+    pub fn save(&self, base_path: &Path) -> Result<()> {
+        let path = Self::path(base_path);
+        fs::write(&path, serde_json::to_string_pretty(self)? + "\n")
+            .context(format!("Failed to write {}", path.display()))
+    }
+}
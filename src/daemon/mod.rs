@@ -1,22 +1,53 @@
 use crate::db::Database;
 use crate::history::HistoryManager;
+use crate::search::ann::Maintenance;
 use anyhow::Result;
 use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
 
+/// How often the daemon checks whether the vector index needs building or updating.
+const INDEX_MAINTENANCE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+pub const PID_FILE: &str = ".stasher/daemon.pid";
+
+/// The pid recorded in the daemon's pid file, if that process is still alive.
+pub fn running_pid(base_path: &Path) -> Option<String> {
+    let pid = std::fs::read_to_string(base_path.join(PID_FILE)).ok()?.trim().to_string();
+    process_alive(&pid).then_some(pid)
+}
+
+#[cfg(unix)]
+fn process_alive(pid: &str) -> bool {
+    std::process::Command::new("kill")
+        .args(["-0", pid])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(true)
+}
+
+/// Without a cheap liveness check, a pid file is taken at its word.
+#[cfg(not(unix))]
+fn process_alive(_pid: &str) -> bool {
+    true
+}
+
 pub struct StasherDaemon {
     history: Arc<HistoryManager>,
     base_path: PathBuf,
+    label: Option<String>,
 }
 
 impl StasherDaemon {
-    pub async fn new(db: Database, base_path: PathBuf) -> Result<Self> {
+    pub async fn new(db: Database, base_path: PathBuf, label: Option<String>) -> Result<Self> {
         let history = HistoryManager::new(Arc::new(db), base_path.clone()).await?;
         Ok(Self {
             history: Arc::new(history),
             base_path,
+            label,
         })
     }
 
@@ -36,20 +67,55 @@ impl StasherDaemon {
         watcher.watch(&self.base_path, RecursiveMode::Recursive)?;
 
         println!("👀 Monitoring changes in: {:?}", self.base_path);
-
-        while let Some(event) = rx.recv().await {
-            self.handle_event(event).await?;
+        self.history.start_session("daemon", self.label.clone()).await?;
+        if let Err(e) = self.history.record_missing_files().await {
+            eprintln!("⚠️ Failed to record files deleted while stopped: {}", e);
         }
 
-        Ok(())
+        // Index maintenance can take a while on big histories, so it runs beside the watcher
+        let mut maintenance = tokio::time::interval(INDEX_MAINTENANCE_INTERVAL);
+        let mut maintaining: Option<tokio::task::JoinHandle<()>> = None;
+
+        // Created once, so a signal that arrives while an event is being handled isn't lost
+        let shutdown = shutdown_signal();
+        tokio::pin!(shutdown);
+
+        let res = loop {
+            tokio::select! {
+                _ = maintenance.tick() => {
+                    if maintaining.as_ref().is_none_or(|task| task.is_finished()) {
+                        maintaining = Some(tokio::spawn(maintain_vector_index(self.history.clone())));
+                    }
+                }
+                event = rx.recv() => match event {
+                    Some(event) => {
+                        if let Err(e) = self.handle_event(event).await {
+                            break Err(e);
+                        }
+                    }
+                    None => break Ok(()),
+                },
+                _ = &mut shutdown => {
+                    println!("👋 Shutting down...");
+                    break Ok(());
+                }
+            }
+        };
+
+        // Close the session even when the loop failed, so its end time is recorded
+        self.history.end_session().await?;
+        res
     }
 
     async fn handle_event(&self, event: notify::Event) -> Result<()> {
-        // We only care about data modifications (file saves)
-        if event.kind.is_modify() {
+        // Saves, plus deletions and the old side of renames, which leave no file behind
+        if event.kind.is_modify() || event.kind.is_remove() {
             for path in event.paths {
                 if self.should_watch(&path) {
                     self.process_file_change(path).await?;
+                } else if !path.exists()
+                    && let Err(e) = self.history.record_deletion(&path).await {
+                    eprintln!("❌ Failed to record deletion of {}: {}", path.display(), e);
                 }
             }
         }
@@ -75,3 +141,36 @@ impl StasherDaemon {
         Ok(())
     }
 }
+
+async fn maintain_vector_index(history: Arc<HistoryManager>) {
+    match history.maintain_vector_index().await {
+        Ok(Maintenance::Unneeded) => {}
+        Ok(Maintenance::Built { rows }) => println!("🗂️ Built vector index over {} chunks", rows),
+        Ok(Maintenance::Retrained { rows }) => println!("🗂️ Retrained vector index over {} chunks", rows),
+        Ok(Maintenance::Optimized { rows }) => println!("🗂️ Updated vector index ({} chunks)", rows),
+        Err(e) => eprintln!("⚠️ Vector index maintenance failed: {:#}", e),
+    }
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM where the platform has it.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = term.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
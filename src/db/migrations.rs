@@ -0,0 +1,220 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use sqlx::SqlitePool;
+use std::path::Path;
+
+/// A single, append-only schema step. Once released, a migration's statements must never change;
+/// fix mistakes with a new migration instead.
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub statements: &'static [&'static str],
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    #[error("{path} uses schema v{found}, but this version of Stasher only understands up to v{supported}. Please upgrade Stasher.")]
+    NewerSchema {
+        path: String,
+        found: i64,
+        supported: i64,
+    },
+}
+
+/// Brings the database at `db_path` up to the latest version in `migrations`.
+///
+/// Migrations are applied in order, each in its own transaction. Before touching a store that
+/// already holds data, a copy is written next to it as `<file>.v<N>.bak`.
+pub async fn run(pool: &SqlitePool, db_path: &Path, migrations: &[Migration]) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied_at INTEGER NOT NULL
+        )"
+    ).execute(pool).await?;
+
+    let current: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(version), 0) FROM schema_version")
+        .fetch_one(pool)
+        .await?;
+    let latest = migrations.last().map_or(0, |m| m.version);
+
+    if current > latest {
+        return Err(MigrationError::NewerSchema {
+            path: db_path.display().to_string(),
+            found: current,
+            supported: latest,
+        }.into());
+    }
+
+    let pending: Vec<&Migration> = migrations.iter().filter(|m| m.version > current).collect();
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    // Stores created before versioning have tables but no schema_version rows
+    let existing_tables: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name != 'schema_version'"
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if existing_tables > 0 {
+        backup(pool, db_path, current).await?;
+    }
+
+    for migration in pending {
+        let mut tx = pool.begin().await?;
+        for statement in migration.statements {
+            sqlx::query(statement)
+                .execute(&mut *tx)
+                .await
+                .context(format!("Migration v{} ({}) failed", migration.version, migration.description))?;
+        }
+        sqlx::query("INSERT INTO schema_version (version, description, applied_at) VALUES (?, ?, ?)")
+            .bind(migration.version)
+            .bind(migration.description)
+            .bind(Utc::now().timestamp_millis())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
+
+/// Fails unless the database at `db_path` is exactly at the latest version in `migrations`,
+/// for opening a store read-only without upgrading it.
+pub async fn check(pool: &SqlitePool, db_path: &Path, migrations: &[Migration]) -> Result<()> {
+    let versioned: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version')")
+        .fetch_one(pool)
+        .await?;
+    let current: i64 = if versioned {
+        sqlx::query_scalar("SELECT COALESCE(MAX(version), 0) FROM schema_version")
+            .fetch_one(pool)
+            .await?
+    } else {
+        0
+    };
+    let latest = migrations.last().map_or(0, |m| m.version);
+
+    if current > latest {
+        return Err(MigrationError::NewerSchema {
+            path: db_path.display().to_string(),
+            found: current,
+            supported: latest,
+        }.into());
+    }
+    if current < latest {
+        anyhow::bail!(
+            "{} uses schema v{}; run any stasher command in that project to upgrade it to v{}",
+            db_path.display(),
+            current,
+            latest
+        );
+    }
+    Ok(())
+}
+
+async fn backup(pool: &SqlitePool, db_path: &Path, version: i64) -> Result<()> {
+    let file_name = db_path.file_name().unwrap_or_default().to_string_lossy();
+    let backup_path = db_path.with_file_name(format!("{}.v{}.bak", file_name, version));
+
+    // VACUUM INTO refuses to overwrite an existing file
+    if backup_path.exists() {
+        std::fs::remove_file(&backup_path)?;
+    }
+
+    sqlx::query("VACUUM INTO ?")
+        .bind(backup_path.to_string_lossy().to_string())
+        .execute(pool)
+        .await
+        .context(format!("Failed to back up {} before migrating", db_path.display()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, run, Migration, MigrationError};
+    use sqlx::sqlite::SqliteConnectOptions;
+    use sqlx::SqlitePool;
+    use std::path::PathBuf;
+
+    const MIGRATIONS: &[Migration] = &[
+        Migration { version: 1, description: "items", statements: &["CREATE TABLE items (id INTEGER PRIMARY KEY)"] },
+        Migration { version: 2, description: "item names", statements: &["ALTER TABLE items ADD COLUMN name TEXT"] },
+    ];
+
+    /// A pool on a fresh database file in its own temp directory.
+    async fn open() -> (SqlitePool, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("stasher-migrations-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.db");
+        let pool = SqlitePool::connect_with(SqliteConnectOptions::new().filename(&path).create_if_missing(true))
+            .await
+            .unwrap();
+        (pool, path)
+    }
+
+    async fn versions(pool: &SqlitePool) -> Vec<i64> {
+        sqlx::query_scalar("SELECT version FROM schema_version ORDER BY version").fetch_all(pool).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn applies_pending_migrations_once() {
+        let (pool, path) = open().await;
+        run(&pool, &path, &MIGRATIONS[..1]).await.unwrap();
+        run(&pool, &path, MIGRATIONS).await.unwrap();
+        run(&pool, &path, MIGRATIONS).await.unwrap();
+
+        assert_eq!(versions(&pool).await, [1, 2]);
+        sqlx::query("INSERT INTO items (name) VALUES ('a')").execute(&pool).await.unwrap();
+        // Upgrading a store with data backs it up first; a fresh one has nothing to keep
+        assert!(path.with_file_name("test.db.v1.bak").exists());
+        assert!(!path.with_file_name("test.db.v0.bak").exists());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_a_newer_schema() {
+        let (pool, path) = open().await;
+        run(&pool, &path, MIGRATIONS).await.unwrap();
+
+        let err = run(&pool, &path, &MIGRATIONS[..1]).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MigrationError>(),
+            Some(MigrationError::NewerSchema { found: 2, supported: 1, .. })
+        ));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rolls_back_a_failed_migration() {
+        let (pool, path) = open().await;
+        let broken = [
+            Migration { version: 1, description: "items", statements: &["CREATE TABLE items (id INTEGER PRIMARY KEY)"] },
+            Migration { version: 2, description: "broken", statements: &["CREATE TABLE tags (name TEXT)", "NOT SQL"] },
+        ];
+
+        assert!(run(&pool, &path, &broken).await.is_err());
+        assert_eq!(versions(&pool).await, [1]);
+        let tags: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE name = 'tags'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(tags, 0);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn check_requires_the_latest_schema() {
+        let (pool, path) = open().await;
+        assert!(check(&pool, &path, MIGRATIONS).await.is_err());
+        run(&pool, &path, &MIGRATIONS[..1]).await.unwrap();
+        assert!(check(&pool, &path, MIGRATIONS).await.is_err());
+        run(&pool, &path, MIGRATIONS).await.unwrap();
+        check(&pool, &path, MIGRATIONS).await.unwrap();
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -3,6 +3,174 @@ use lancedb::connect;
 use sqlx::{sqlite::SqliteConnectOptions, SqlitePool};
 use std::path::Path;
 
+pub mod migrations;
+
+use migrations::Migration;
+
+/// Schema history for `metadata.db`. Append new entries; never edit released ones.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial sessions and snapshots tables",
+        statements: &[
+            "CREATE TABLE IF NOT EXISTS sessions (
+                id TEXT PRIMARY KEY,
+                start_time INTEGER NOT NULL,
+                end_time INTEGER,
+                meta TEXT
+            )",
+            "CREATE TABLE IF NOT EXISTS snapshots (
+                id TEXT PRIMARY KEY,
+                session_id TEXT NOT NULL,
+                file_path TEXT NOT NULL,
+                timestamp INTEGER NOT NULL,
+                diff_patch TEXT NOT NULL,
+                content_hash TEXT NOT NULL,
+                lines_added INTEGER NOT NULL,
+                lines_removed INTEGER NOT NULL,
+                FOREIGN KEY(session_id) REFERENCES sessions(id)
+            )",
+            "CREATE INDEX IF NOT EXISTS idx_snapshots_file ON snapshots(file_path)",
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "session labels, branch and trigger",
+        statements: &[
+            "ALTER TABLE sessions ADD COLUMN label TEXT",
+            "ALTER TABLE sessions ADD COLUMN branch TEXT",
+            "ALTER TABLE sessions ADD COLUMN trigger_kind TEXT",
+            "CREATE INDEX IF NOT EXISTS idx_snapshots_session ON snapshots(session_id)",
+            // Older builds opened a session for every command; drop the empty ones
+            // and close the rest at their last save.
+            "DELETE FROM sessions WHERE id NOT IN (SELECT DISTINCT session_id FROM snapshots)",
+            "UPDATE sessions SET end_time = (SELECT MAX(timestamp) FROM snapshots WHERE session_id = sessions.id)
+             WHERE end_time IS NULL",
+        ],
+    },
+    Migration {
+        version: 3,
+        description: "tags",
+        statements: &[
+            "CREATE TABLE IF NOT EXISTS tags (
+                name TEXT PRIMARY KEY,
+                timestamp INTEGER NOT NULL,
+                all_files INTEGER NOT NULL,
+                created_at INTEGER NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS tag_snapshots (
+                tag_name TEXT NOT NULL,
+                snapshot_id TEXT NOT NULL,
+                PRIMARY KEY (tag_name, snapshot_id),
+                FOREIGN KEY(tag_name) REFERENCES tags(name)
+            )",
+            "CREATE INDEX IF NOT EXISTS idx_tag_snapshots_snapshot ON tag_snapshots(snapshot_id)",
+        ],
+    },
+    Migration {
+        version: 4,
+        description: "notes with full-text index",
+        statements: &[
+            "CREATE TABLE IF NOT EXISTS notes (
+                id TEXT PRIMARY KEY,
+                target_kind TEXT NOT NULL,
+                target_id TEXT NOT NULL,
+                body TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            )",
+            "CREATE INDEX IF NOT EXISTS idx_notes_target ON notes(target_kind, target_id)",
+            "CREATE VIRTUAL TABLE IF NOT EXISTS notes_fts USING fts5(note_id UNINDEXED, body)",
+        ],
+    },
+    Migration {
+        version: 5,
+        description: "full-text index over search chunks",
+        statements: &[
+            // Underscores are token characters so identifiers like `parse_jwt_claims` match whole
+            "CREATE VIRTUAL TABLE IF NOT EXISTS chunks_fts USING fts5(
+                snapshot_id UNINDEXED,
+                file_path UNINDEXED,
+                kind UNINDEXED,
+                start_line UNINDEXED,
+                end_line UNINDEXED,
+                content,
+                tokenize = \"unicode61 tokenchars '_'\"
+            )",
+        ],
+    },
+    Migration {
+        version: 6,
+        description: "key search chunks by content hash",
+        statements: &[
+            // The old rows are per snapshot; the vector table is rebuilt empty alongside, so start over
+            "DROP TABLE IF EXISTS chunks_fts",
+            "CREATE VIRTUAL TABLE chunks_fts USING fts5(
+                key UNINDEXED,
+                kind UNINDEXED,
+                start_line UNINDEXED,
+                end_line UNINDEXED,
+                content,
+                tokenize = \"unicode61 tokenchars '_'\"
+            )",
+            "CREATE TABLE IF NOT EXISTS indexed_content (content_hash TEXT PRIMARY KEY)",
+        ],
+    },
+    Migration {
+        version: 7,
+        description: "settings, including the live vector index generation",
+        statements: &[
+            "CREATE TABLE IF NOT EXISTS settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            )",
+        ],
+    },
+    Migration {
+        version: 8,
+        description: "journal of restores and checkouts, for undo",
+        statements: &[
+            // status: pending while files are being written, then applied, rolled_back or undone
+            "CREATE TABLE IF NOT EXISTS restore_journal (
+                id TEXT PRIMARY KEY,
+                command TEXT NOT NULL,
+                reference TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                status TEXT NOT NULL
+            )",
+            // before_hash is NULL when the restore created the file
+            "CREATE TABLE IF NOT EXISTS restore_journal_files (
+                journal_id TEXT NOT NULL,
+                file_path TEXT NOT NULL,
+                before_hash TEXT,
+                after_hash TEXT NOT NULL,
+                PRIMARY KEY (journal_id, file_path),
+                FOREIGN KEY(journal_id) REFERENCES restore_journal(id)
+            )",
+        ],
+    },
+    Migration {
+        version: 9,
+        description: "index snapshots by content hash, for filtered search",
+        statements: &[
+            "CREATE INDEX IF NOT EXISTS idx_snapshots_content ON snapshots(content_hash)",
+        ],
+    },
+    Migration {
+        version: 10,
+        description: "record deleted and moved-away files",
+        statements: &[
+            "CREATE TABLE IF NOT EXISTS file_deletions (
+                id TEXT PRIMARY KEY,
+                session_id TEXT NOT NULL,
+                file_path TEXT NOT NULL,
+                timestamp INTEGER NOT NULL,
+                FOREIGN KEY(session_id) REFERENCES sessions(id)
+            )",
+            "CREATE INDEX IF NOT EXISTS idx_file_deletions_path ON file_deletions(file_path, timestamp)",
+        ],
+    },
+];
+
 pub struct Database {
     pub sqlite: SqlitePool,
     pub lancedb: lancedb::Connection,
@@ -28,7 +196,7 @@ impl Database {
             .context("Failed to connect to SQLite")?;
 
         // Run migrations
-        Self::run_migrations(&sqlite).await?;
+        migrations::run(&sqlite, &sqlite_path, MIGRATIONS).await?;
 
         // 2. Initialize LanceDB
         let vector_path = stasher_dir.join("vectors");
@@ -39,34 +207,36 @@ impl Database {
         Ok(Self { sqlite, lancedb })
     }
 
-    async fn run_migrations(pool: &SqlitePool) -> Result<()> {
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS sessions (
-                id TEXT PRIMARY KEY,
-                start_time INTEGER NOT NULL,
-                end_time INTEGER,
-                meta TEXT
-            )"
-        ).execute(pool).await?;
+    /// Opens an existing project's stores for reading only: nothing is created, migrated or
+    /// written. Fails if the project's schema isn't the one this version of Stasher writes.
+    pub async fn open_read_only(base_path: &Path) -> Result<Self> {
+        let stasher_dir = base_path.join(".stasher");
+        let sqlite_path = stasher_dir.join("metadata.db");
+        if !sqlite_path.exists() {
+            anyhow::bail!("{} has no Stasher history", base_path.display());
+        }
 
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS snapshots (
-                id TEXT PRIMARY KEY,
-                session_id TEXT NOT NULL,
-                file_path TEXT NOT NULL,
-                timestamp INTEGER NOT NULL,
-                diff_patch TEXT NOT NULL,
-                content_hash TEXT NOT NULL,
-                lines_added INTEGER NOT NULL,
-                lines_removed INTEGER NOT NULL,
-                FOREIGN KEY(session_id) REFERENCES sessions(id)
-            )"
-        ).execute(pool).await?;
+        let conn_options = SqliteConnectOptions::new()
+            .filename(&sqlite_path)
+            .read_only(true);
+        let sqlite = SqlitePool::connect_with(conn_options)
+            .await
+            .context("Failed to connect to SQLite")?;
+        migrations::check(&sqlite, &sqlite_path, MIGRATIONS).await?;
 
-        sqlx::query(
-            "CREATE INDEX IF NOT EXISTS idx_snapshots_file ON snapshots(file_path)"
-        ).execute(pool).await?;
+        let vector_path = stasher_dir.join("vectors");
+        let lancedb = connect(&vector_path.to_string_lossy()).execute().await?;
 
-        Ok(())
+        Ok(Self { sqlite, lancedb })
     }
 }
+
+/// Turns free text into an FTS5 `MATCH` expression that matches any of its words,
+/// quoting each one so punctuation in the input can't be read as query syntax.
+pub fn fts5_query(text: &str) -> String {
+    text.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("\"{}\"", word))
+        .collect::<Vec<_>>()
+        .join(" OR ")
+}
@@ -0,0 +1,234 @@
+//! Terminal rendering of file diffs: unified or side by side, with the words that changed
+//! inside a modified line highlighted, and optionally blind to whitespace and re-wrapping.
+
+use colored::{ColoredString, Colorize};
+use similar::{capture_diff_slices, group_diff_ops, Algorithm, ChangeTag, DiffOp, DiffTag, TextDiff};
+
+/// Lines of unchanged context around each hunk.
+const CONTEXT_LINES: usize = 3;
+/// Lines less alike than this are coloured whole; highlighting words in them is just noise.
+const INLINE_SIMILARITY: f32 = 0.5;
+
+#[derive(Clone, Copy, Default)]
+pub struct DiffStyle {
+    pub side_by_side: bool,
+    /// Ignore changes to whitespace, indentation and where lines are wrapped.
+    pub ignore_whitespace: bool,
+}
+
+/// A piece of a line and whether it's part of what changed.
+type Segment = (String, bool);
+
+/// Prints the hunks that turn `old` into `new`. Returns false when, in the chosen style,
+/// nothing changed.
+pub fn print_diff(old: &str, new: &str, style: &DiffStyle) -> bool {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let key = |line: &&str| -> String {
+        if style.ignore_whitespace {
+            line.split_whitespace().collect()
+        } else {
+            line.to_string()
+        }
+    };
+    let old_keys: Vec<String> = old_lines.iter().map(key).collect();
+    let new_keys: Vec<String> = new_lines.iter().map(key).collect();
+
+    let ops = capture_diff_slices(Algorithm::Myers, &old_keys, &new_keys);
+    let mut printed = false;
+    for group in group_diff_ops(ops, CONTEXT_LINES) {
+        printed |= print_group(&old_lines, &new_lines, &old_keys, &new_keys, &group, style);
+    }
+    printed
+}
+
+/// Prints one hunk of a diff computed elsewhere, over the same lines.
+pub fn print_hunk(old_lines: &[&str], new_lines: &[&str], group: &[DiffOp], style: &DiffStyle) {
+    let old_lines: Vec<&str> = old_lines.iter().map(|l| l.trim_end_matches(['\n', '\r'])).collect();
+    let new_lines: Vec<&str> = new_lines.iter().map(|l| l.trim_end_matches(['\n', '\r'])).collect();
+    let old_keys: Vec<String> = old_lines.iter().map(|l| l.to_string()).collect();
+    let new_keys: Vec<String> = new_lines.iter().map(|l| l.to_string()).collect();
+    let style = DiffStyle { ignore_whitespace: false, ..*style };
+    print_group(&old_lines, &new_lines, &old_keys, &new_keys, group, &style);
+}
+
+fn print_group(old_lines: &[&str], new_lines: &[&str], old_keys: &[String], new_keys: &[String], group: &[DiffOp], style: &DiffStyle) -> bool {
+    let changes = group.iter()
+        .filter(|op| op.tag() != DiffTag::Equal && !(style.ignore_whitespace && is_reflow(op, old_keys, new_keys)))
+        .count();
+    if changes == 0 {
+        return false;
+    }
+
+    let (first, last) = (&group[0], &group[group.len() - 1]);
+    println!("{}", format!(
+        "@@ -{},{} +{},{} @@",
+        first.old_range().start + 1,
+        last.old_range().end - first.old_range().start,
+        first.new_range().start + 1,
+        last.new_range().end - first.new_range().start,
+    ).blue());
+
+    let hunk = Hunk { old: old_lines, new: new_lines, old_keys, new_keys, style };
+    for op in group {
+        if style.side_by_side {
+            hunk.print_side_by_side(op);
+        } else {
+            hunk.print_unified(op);
+        }
+    }
+    true
+}
+
+/// Lines that only moved text between them, e.g. one call wrapped over three lines.
+fn is_reflow(op: &DiffOp, old_keys: &[String], new_keys: &[String]) -> bool {
+    op.tag() == DiffTag::Replace
+        && old_keys[op.old_range()].concat() == new_keys[op.new_range()].concat()
+}
+
+struct Hunk<'a> {
+    old: &'a [&'a str],
+    new: &'a [&'a str],
+    old_keys: &'a [String],
+    new_keys: &'a [String],
+    style: &'a DiffStyle,
+}
+
+impl Hunk<'_> {
+    fn is_context(&self, op: &DiffOp) -> bool {
+        op.tag() == DiffTag::Equal || (self.style.ignore_whitespace && is_reflow(op, self.old_keys, self.new_keys))
+    }
+
+    fn print_unified(&self, op: &DiffOp) {
+        if self.is_context(op) {
+            // Show the new text, which may differ from the old in whitespace alone
+            for line in &self.new[op.new_range()] {
+                println!(" {}", line);
+            }
+            return;
+        }
+
+        let old = &self.old[op.old_range()];
+        let new = &self.new[op.new_range()];
+        let paired = old.len().min(new.len());
+        let mut old_rows: Vec<Vec<Segment>> = old.iter().map(|l| vec![(l.to_string(), false)]).collect();
+        let mut new_rows: Vec<Vec<Segment>> = new.iter().map(|l| vec![(l.to_string(), false)]).collect();
+        for i in 0..paired {
+            if let Some((o, n)) = inline_segments(old[i], new[i]) {
+                old_rows[i] = o;
+                new_rows[i] = n;
+            }
+        }
+        for row in old_rows {
+            println!("{}{}", "-".red(), styled(&row, ChangeTag::Delete));
+        }
+        for row in new_rows {
+            println!("{}{}", "+".green(), styled(&row, ChangeTag::Insert));
+        }
+    }
+
+    fn print_side_by_side(&self, op: &DiffOp) {
+        let width = terminal_width();
+        let number_width = self.old.len().max(self.new.len()).to_string().len();
+        let column = (width.saturating_sub(2 * number_width + 5) / 2).max(20);
+
+        let old_range = op.old_range();
+        let new_range = op.new_range();
+        let context = self.is_context(op);
+        let rows = old_range.len().max(new_range.len());
+        for i in 0..rows {
+            let old_line = old_range.clone().nth(i).map(|n| (n, self.old[n]));
+            let new_line = new_range.clone().nth(i).map(|n| (n, self.new[n]));
+
+            let (old_segments, new_segments) = match (old_line, new_line) {
+                (Some((_, o)), Some((_, n))) if !context => inline_segments(o, n)
+                    .unwrap_or_else(|| (vec![(o.to_string(), false)], vec![(n.to_string(), false)])),
+                _ => (
+                    old_line.map(|(_, o)| vec![(o.to_string(), false)]).unwrap_or_default(),
+                    new_line.map(|(_, n)| vec![(n.to_string(), false)]).unwrap_or_default(),
+                ),
+            };
+            let (old_tag, new_tag) = if context {
+                (ChangeTag::Equal, ChangeTag::Equal)
+            } else {
+                (ChangeTag::Delete, ChangeTag::Insert)
+            };
+
+            let number = |line: Option<(usize, &str)>| match line {
+                Some((n, _)) => format!("{:>w$}", n + 1, w = number_width),
+                None => " ".repeat(number_width),
+            };
+            let (left, left_len) = fit(&old_segments, column, old_tag);
+            println!(
+                "{} {}{} {} {} {}",
+                number(old_line).dimmed(),
+                left,
+                " ".repeat(column - left_len),
+                "│".dimmed(),
+                number(new_line).dimmed(),
+                fit(&new_segments, column, new_tag).0,
+            );
+        }
+    }
+}
+
+/// Splits a modified line pair into the words they share and the words that changed, or
+/// `None` when the lines have too little in common for that to help.
+fn inline_segments(old: &str, new: &str) -> Option<(Vec<Segment>, Vec<Segment>)> {
+    let diff = TextDiff::from_words(old, new);
+    if diff.ratio() < INLINE_SIMILARITY {
+        return None;
+    }
+    let mut old_segments = Vec::new();
+    let mut new_segments = Vec::new();
+    for change in diff.iter_all_changes() {
+        let value = change.value().to_string();
+        match change.tag() {
+            ChangeTag::Equal => {
+                old_segments.push((value.clone(), false));
+                new_segments.push((value, false));
+            }
+            ChangeTag::Delete => old_segments.push((value, true)),
+            ChangeTag::Insert => new_segments.push((value, true)),
+        }
+    }
+    Some((old_segments, new_segments))
+}
+
+fn paint(text: &str, tag: ChangeTag, emphasis: bool) -> ColoredString {
+    match (tag, emphasis) {
+        (ChangeTag::Delete, false) => text.red(),
+        (ChangeTag::Delete, true) => text.white().on_red(),
+        (ChangeTag::Insert, false) => text.green(),
+        (ChangeTag::Insert, true) => text.black().on_green(),
+        (ChangeTag::Equal, _) => text.normal(),
+    }
+}
+
+fn styled(segments: &[Segment], tag: ChangeTag) -> String {
+    segments.iter().map(|(text, emphasis)| paint(text, tag, *emphasis).to_string()).collect()
+}
+
+/// Styles `segments`, cut to `width` characters. Returns the text and how many columns it
+/// takes, since the escape codes make `len()` useless for padding.
+fn fit(segments: &[Segment], width: usize, tag: ChangeTag) -> (String, usize) {
+    let mut out = String::new();
+    let mut used = 0;
+    for (text, emphasis) in segments {
+        let text = text.replace('\t', "    ");
+        let take: String = text.chars().take(width - used).collect();
+        used += take.chars().count();
+        out.push_str(&paint(&take, tag, *emphasis).to_string());
+        if used == width {
+            break;
+        }
+    }
+    (out, used)
+}
+
+fn terminal_width() -> usize {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .unwrap_or(160)
+}
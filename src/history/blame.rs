@@ -0,0 +1,102 @@
+use super::HistoryManager;
+use anyhow::Result;
+use similar::{ChangeTag, TextDiff};
+use std::collections::HashMap;
+use std::fs;
+
+/// The save that last changed a line.
+pub struct LineOrigin {
+    pub snapshot_id: String,
+    pub session_id: String,
+    pub timestamp: i64,
+    /// The path the file had then, which differs from today's after a move.
+    pub file_path: String,
+}
+
+pub struct Blame {
+    /// Every snapshot some line is attributed to, oldest first.
+    pub origins: Vec<LineOrigin>,
+    /// The current lines, each with an index into `origins`, or `None` for lines not saved
+    /// in any snapshot yet.
+    pub lines: Vec<(String, Option<usize>)>,
+    /// Whether `lines` come from the working copy rather than the newest snapshot.
+    pub from_working_copy: bool,
+}
+
+impl HistoryManager {
+    /// Attributes each line of a file to the snapshot that last changed it, replaying the file's
+    /// versions oldest first (following moves) and carrying attributions through unchanged lines.
+    pub async fn blame(&self, file_path: &str) -> Result<Blame> {
+        let mut snapshots = self.list_snapshots(file_path).await?;
+        if snapshots.is_empty() {
+            anyhow::bail!("No history found for file: {}", file_path);
+        }
+        snapshots.reverse();
+
+        let ids: Vec<&str> = snapshots.iter().map(|s| s.id.as_str()).collect();
+        let sessions: HashMap<String, String> = sqlx::query_as(
+            "SELECT id, session_id FROM snapshots WHERE id IN (SELECT value FROM json_each(?))"
+        )
+        .bind(serde_json::to_string(&ids)?)
+        .fetch_all(&self.db.sqlite)
+        .await?
+        .into_iter()
+        .collect();
+
+        let mut origins: Vec<LineOrigin> = Vec::new();
+        let mut previous = String::new();
+        let mut attribution: Vec<Option<usize>> = Vec::new();
+        for snapshot in snapshots {
+            // A version whose object is gone can't be replayed; the next one is diffed against
+            // the last we could read
+            let Ok(content) = fs::read_to_string(self.objects_path.join(&snapshot.content_hash)) else {
+                continue;
+            };
+            origins.push(LineOrigin {
+                session_id: sessions.get(&snapshot.id).cloned().unwrap_or_default(),
+                snapshot_id: snapshot.id,
+                timestamp: snapshot.timestamp,
+                file_path: snapshot.file_path,
+            });
+            attribution = carry_over(&previous, &content, &attribution, Some(origins.len() - 1));
+            previous = content;
+        }
+
+        if origins.is_empty() {
+            anyhow::bail!("Every stored version of {} is missing from the object store", file_path);
+        }
+
+        let working_path = self.base_path.join(self.to_stasher_relative(file_path));
+        let (content, from_working_copy) = match fs::read_to_string(&working_path) {
+            Ok(current) => {
+                attribution = carry_over(&previous, &current, &attribution, None);
+                (current, true)
+            }
+            Err(_) => (previous, false),
+        };
+
+        let lines = content.lines()
+            .map(str::to_string)
+            .zip(attribution)
+            .collect();
+        Ok(Blame { origins, lines, from_working_copy })
+    }
+}
+
+/// Maps `old`'s per-line attribution onto `new`: unchanged lines keep theirs and inserted lines
+/// get `inserted_by`.
+fn carry_over(old: &str, new: &str, attribution: &[Option<usize>], inserted_by: Option<usize>) -> Vec<Option<usize>> {
+    let diff = TextDiff::from_lines(old, new);
+    let mut result = Vec::with_capacity(new.lines().count());
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Equal => {
+                let old_index = change.old_index().expect("equal lines have an old index");
+                result.push(attribution.get(old_index).copied().flatten());
+            }
+            ChangeTag::Insert => result.push(inserted_by),
+            ChangeTag::Delete => {}
+        }
+    }
+    result
+}
@@ -0,0 +1,312 @@
+use super::tags::Reference;
+use super::tree::TreeEntry;
+use super::HistoryManager;
+use anyhow::{Context, Result};
+use similar::TextDiff;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+/// Renames below this similarity are reported as a removal plus an addition.
+const RENAME_SIMILARITY: f32 = 0.5;
+/// Past this many removed × added pairs, only exact-content renames are detected.
+const RENAME_PAIR_LIMIT: usize = 10_000;
+
+/// One side of a comparison.
+pub enum DiffSide {
+    /// A single file version, from a snapshot id or single-snapshot tag.
+    Snapshot(TreeEntry),
+    /// A whole project state, from a tag covering all files or a point in time.
+    Tree(Vec<TreeEntry>),
+    /// The files on disk now.
+    Working,
+}
+
+pub enum FileChange {
+    Added { path: String, content: String },
+    Removed { path: String, content: String },
+    Modified { path: String, old: String, new: String },
+    /// `similarity` is 1.0 for a pure move.
+    Renamed { from: String, to: String, old: String, new: String, similarity: f32 },
+}
+
+impl HistoryManager {
+    /// Decides what a reference stands for: a snapshot id or single-snapshot tag is one file
+    /// version; a tag covering all files or a point in time is a whole project state.
+    pub async fn resolve_side(&self, reference: &str) -> Result<DiffSide> {
+        let all_files: Option<bool> = sqlx::query_scalar("SELECT all_files FROM tags WHERE name = ?")
+            .bind(reference)
+            .fetch_optional(&self.db.sqlite)
+            .await?;
+        let id = match all_files {
+            Some(true) => None,
+            Some(false) => Some(self.resolve_snapshot(reference, None).await?),
+            None => match self.classify_reference(reference).await? {
+                Reference::Snapshot(id) => Some(id),
+                Reference::Time(_) => None,
+            },
+        };
+
+        match id {
+            Some(id) => {
+                let entry = sqlx::query_as::<_, TreeEntry>("SELECT id AS snapshot_id, file_path, content_hash FROM snapshots WHERE id = ?")
+                    .bind(&id)
+                    .fetch_one(&self.db.sqlite)
+                    .await?;
+                Ok(DiffSide::Snapshot(entry))
+            }
+            None => Ok(DiffSide::Tree(self.resolve_tree(reference).await?)),
+        }
+    }
+
+    /// Compares two states, limited to `paths` (files or directories) when any are given.
+    /// A single file version compared with a whole state is compared with that state's version
+    /// of the same file. Files removed on one side and added on the other with similar content
+    /// are reported as renames.
+    pub async fn compare(&self, old: DiffSide, new: DiffSide, paths: &[String]) -> Result<Vec<FileChange>> {
+        let prefixes: Vec<String> = paths.iter().map(|p| self.to_stasher_relative(p)).collect();
+        let selected = |path: &str| {
+            prefixes.is_empty() || prefixes.iter().any(|p| {
+                path == p || path.starts_with(&format!("{}/", p.trim_end_matches('/')))
+            })
+        };
+
+        // Two single versions are compared directly, whatever their paths
+        if let (DiffSide::Snapshot(a), DiffSide::Snapshot(b)) = (&old, &new) {
+            let change = if a.file_path == b.file_path {
+                FileChange::Modified { path: b.file_path.clone(), old: self.read_object(a)?, new: self.read_object(b)? }
+            } else {
+                let (old, new) = (self.read_object(a)?, self.read_object(b)?);
+                let similarity = TextDiff::from_lines(&old, &new).ratio();
+                FileChange::Renamed { from: a.file_path.clone(), to: b.file_path.clone(), old, new, similarity }
+            };
+            return Ok(vec![change]);
+        }
+
+        let focus = match (&old, &new) {
+            (DiffSide::Snapshot(entry), _) | (_, DiffSide::Snapshot(entry)) => Some(entry.file_path.clone()),
+            _ => None,
+        };
+        let keep = |path: &str| selected(path) && focus.as_deref().is_none_or(|f| f == path);
+
+        let old_files = self.side_files(old, &keep)?;
+        let new_files = self.side_files(new, &keep)?;
+
+        let mut changes = Vec::new();
+        let mut removed: Vec<(String, String)> = Vec::new();
+        let mut added: Vec<(String, String)> = Vec::new();
+        for (path, old_content) in &old_files {
+            match new_files.get(path) {
+                Some(new_content) if new_content != old_content => changes.push(FileChange::Modified {
+                    path: path.clone(),
+                    old: old_content.clone(),
+                    new: new_content.clone(),
+                }),
+                Some(_) => {}
+                None => removed.push((path.clone(), old_content.clone())),
+            }
+        }
+        for (path, new_content) in new_files {
+            if !old_files.contains_key(&path) {
+                added.push((path, new_content));
+            }
+        }
+
+        changes.extend(detect_renames(&mut removed, &mut added));
+        changes.extend(removed.into_iter().map(|(path, content)| FileChange::Removed { path, content }));
+        changes.extend(added.into_iter().map(|(path, content)| FileChange::Added { path, content }));
+        changes.sort_by(|a, b| a.path().cmp(b.path()));
+        Ok(changes)
+    }
+
+    /// The text of every file on one side that passes `keep`, by path. Binary files on disk are
+    /// skipped; a stored version that can't be read is an error rather than a missing file.
+    fn side_files(&self, side: DiffSide, keep: &dyn Fn(&str) -> bool) -> Result<BTreeMap<String, String>> {
+        let mut files = BTreeMap::new();
+        match side {
+            DiffSide::Snapshot(entry) => {
+                files.insert(entry.file_path.clone(), self.read_object(&entry)?);
+            }
+            DiffSide::Tree(entries) => {
+                for entry in entries.into_iter().filter(|e| keep(&e.file_path)) {
+                    let content = self.read_object(&entry)?;
+                    files.insert(entry.file_path, content);
+                }
+            }
+            DiffSide::Working => {
+                for entry in ignore::WalkBuilder::new(&self.base_path).standard_filters(true).build().flatten() {
+                    let path = entry.path();
+                    if !path.is_file() || self.is_internal_path(path) {
+                        continue;
+                    }
+                    let relative = path.strip_prefix(&self.base_path).unwrap_or(path).to_string_lossy().to_string();
+                    if keep(&relative)
+                        && let Ok(content) = fs::read_to_string(path) {
+                        files.insert(relative, content);
+                    }
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// The file one snapshot saved, as `(path, before, after)`: before is the previous
+    /// snapshot of the same path, or empty when this snapshot created the file. `None` when
+    /// pruning has removed either version from the object store.
+    pub async fn snapshot_versions(&self, reference: &str) -> Result<Option<(String, String, String)>> {
+        let id = self.resolve_snapshot(reference, None).await?;
+        let entry = sqlx::query_as::<_, TreeEntry>("SELECT id AS snapshot_id, file_path, content_hash FROM snapshots WHERE id = ?")
+            .bind(&id)
+            .fetch_one(&self.db.sqlite)
+            .await?;
+        let previous = sqlx::query_as::<_, TreeEntry>(
+            "SELECT p.id AS snapshot_id, p.file_path, p.content_hash FROM snapshots p, snapshots s
+             WHERE s.id = ? AND p.file_path = s.file_path AND p.timestamp < s.timestamp
+             ORDER BY p.timestamp DESC LIMIT 1",
+        )
+            .bind(&id)
+            .fetch_optional(&self.db.sqlite)
+            .await?;
+
+        let before = match previous {
+            Some(previous) => match self.read_stored(&previous)? {
+                Some(before) => before,
+                None => return Ok(None),
+            },
+            None => String::new(),
+        };
+        let Some(after) = self.read_stored(&entry)? else {
+            return Ok(None);
+        };
+        Ok(Some((entry.file_path, before, after)))
+    }
+
+    fn read_object(&self, entry: &TreeEntry) -> Result<String> {
+        fs::read_to_string(self.objects_path.join(&entry.content_hash))
+            .context(format!("Failed to read historical object for {}", entry.file_path))
+    }
+
+    /// Like `read_object`, but a missing object is `None` rather than an error.
+    fn read_stored(&self, entry: &TreeEntry) -> Result<Option<String>> {
+        match fs::read_to_string(self.objects_path.join(&entry.content_hash)) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).context(format!("Failed to read historical object for {}", entry.file_path)),
+        }
+    }
+}
+
+impl FileChange {
+    /// The path the change is listed under: the new one for renames.
+    pub fn path(&self) -> &str {
+        match self {
+            FileChange::Added { path, .. } | FileChange::Removed { path, .. } | FileChange::Modified { path, .. } => path,
+            FileChange::Renamed { to, .. } => to,
+        }
+    }
+}
+
+/// Pairs removed files with added ones: identical content first, then the most similar pair
+/// above `RENAME_SIMILARITY`. Paired files are taken out of `removed` and `added`.
+fn detect_renames(removed: &mut Vec<(String, String)>, added: &mut Vec<(String, String)>) -> Vec<FileChange> {
+    let mut renames = Vec::new();
+
+    let mut by_content: HashMap<&str, usize> = HashMap::new();
+    for (i, (_, content)) in added.iter().enumerate() {
+        by_content.entry(content.as_str()).or_insert(i);
+    }
+    let mut pairs: Vec<(usize, usize, f32)> = Vec::new();
+    for (r, (_, content)) in removed.iter().enumerate() {
+        if let Some(&a) = by_content.get(content.as_str()) {
+            by_content.remove(content.as_str());
+            pairs.push((r, a, 1.0));
+        }
+    }
+
+    if removed.len() * added.len() <= RENAME_PAIR_LIMIT {
+        let mut candidates = Vec::new();
+        for (r, (_, old)) in removed.iter().enumerate() {
+            for (a, (_, new)) in added.iter().enumerate() {
+                if pairs.iter().any(|&(pr, pa, _)| pr == r || pa == a) {
+                    continue;
+                }
+                let similarity = TextDiff::from_lines(old, new).ratio();
+                if similarity >= RENAME_SIMILARITY {
+                    candidates.push((r, a, similarity));
+                }
+            }
+        }
+        candidates.sort_by(|x, y| y.2.total_cmp(&x.2));
+        for (r, a, similarity) in candidates {
+            if !pairs.iter().any(|&(pr, pa, _)| pr == r || pa == a) {
+                pairs.push((r, a, similarity));
+            }
+        }
+    }
+
+    // Remove from the back so earlier indices stay valid
+    let (mut taken_removed, mut taken_added): (Vec<usize>, Vec<usize>) = pairs.iter().map(|&(r, a, _)| (r, a)).unzip();
+    let mut old_files: HashMap<usize, (String, String)> = HashMap::new();
+    let mut new_files: HashMap<usize, (String, String)> = HashMap::new();
+    taken_removed.sort_unstable_by(|a, b| b.cmp(a));
+    taken_added.sort_unstable_by(|a, b| b.cmp(a));
+    for r in taken_removed {
+        old_files.insert(r, removed.remove(r));
+    }
+    for a in taken_added {
+        new_files.insert(a, added.remove(a));
+    }
+    for (r, a, similarity) in pairs {
+        let (from, old) = old_files.remove(&r).expect("paired once");
+        let (to, new) = new_files.remove(&a).expect("paired once");
+        renames.push(FileChange::Renamed { from, to, old, new, similarity });
+    }
+    renames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect_renames, FileChange};
+
+    fn files(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries.iter().map(|(path, content)| (path.to_string(), content.to_string())).collect()
+    }
+
+    fn renames(changes: Vec<FileChange>) -> Vec<(String, String, f32)> {
+        changes
+            .into_iter()
+            .map(|change| match change {
+                FileChange::Renamed { from, to, similarity, .. } => (from, to, similarity),
+                _ => panic!("detect_renames only reports renames"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pairs_identical_content_as_a_move() {
+        let mut removed = files(&[("a.rs", "x\ny\n")]);
+        let mut added = files(&[("b.rs", "x\ny\n")]);
+        assert_eq!(renames(detect_renames(&mut removed, &mut added)), vec![("a.rs".to_string(), "b.rs".to_string(), 1.0)]);
+        assert!(removed.is_empty() && added.is_empty());
+    }
+
+    #[test]
+    fn pairs_the_most_similar_file_and_leaves_the_rest() {
+        let mut removed = files(&[("a.rs", "1\n2\n3\n4\n")]);
+        let mut added = files(&[("c.rs", "1\n2\nX\nY\n"), ("b.rs", "1\n2\n3\nX\n")]);
+        let found = renames(detect_renames(&mut removed, &mut added));
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].0.as_str(), found[0].1.as_str()), ("a.rs", "b.rs"));
+        assert!(found[0].2 > 0.5 && found[0].2 < 1.0);
+        assert!(removed.is_empty());
+        assert_eq!(added, files(&[("c.rs", "1\n2\nX\nY\n")]));
+    }
+
+    #[test]
+    fn leaves_dissimilar_files_unpaired() {
+        let mut removed = files(&[("a.rs", "1\n2\n")]);
+        let mut added = files(&[("b.rs", "x\ny\n")]);
+        assert!(detect_renames(&mut removed, &mut added).is_empty());
+        assert_eq!(removed.len(), 1);
+        assert_eq!(added.len(), 1);
+    }
+}
@@ -0,0 +1,117 @@
+use super::HistoryManager;
+use crate::search::SearchFilters;
+use anyhow::Result;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+
+/// A matching line of one file, tracked across every version it appeared in.
+pub struct GrepHit {
+    pub file_path: String,
+    pub line: String,
+    /// 1-based, in the newest version containing the line.
+    pub line_number: usize,
+    pub first_seen: i64,
+    pub first_snapshot: String,
+    pub last_seen: i64,
+    pub last_snapshot: String,
+    /// How many snapshots of the file contained the line.
+    pub snapshots: usize,
+    /// Whether the file's newest scanned version still contains the line.
+    pub still_present: bool,
+}
+
+#[derive(Default)]
+pub struct GrepStats {
+    /// Distinct file versions read from the object store.
+    pub objects: usize,
+    /// Versions whose object is missing or isn't text.
+    pub unreadable: usize,
+}
+
+#[derive(sqlx::FromRow)]
+struct VersionRow {
+    id: String,
+    file_path: String,
+    timestamp: i64,
+    content_hash: String,
+}
+
+impl HistoryManager {
+    /// Runs `pattern` over every stored version of every file, reading each distinct object
+    /// once. Works from the object store alone, so it finds text the search index never saw.
+    pub async fn grep(&self, pattern: &Regex, filters: &SearchFilters) -> Result<(Vec<GrepHit>, GrepStats)> {
+        let mut sql = sqlx::QueryBuilder::new(
+            "SELECT s.id, s.file_path, s.timestamp, s.content_hash
+             FROM snapshots s
+             LEFT JOIN sessions se ON se.id = s.session_id
+             WHERE 1 = 1"
+        );
+        filters.push_sql(&mut sql);
+        sql.push(" ORDER BY s.timestamp ASC");
+        let rows: Vec<VersionRow> = sql.build_query_as().fetch_all(&self.db.sqlite).await?;
+
+        let mut stats = GrepStats::default();
+        let mut matches_by_hash: HashMap<String, Vec<(usize, String)>> = HashMap::new();
+        let mut hits: HashMap<(String, String), GrepHit> = HashMap::new();
+        let mut newest: HashMap<String, String> = HashMap::new();
+
+        for row in rows.iter().filter(|r| filters.keeps(&r.file_path)) {
+            newest.insert(row.file_path.clone(), row.id.clone());
+            if !matches_by_hash.contains_key(&row.content_hash) {
+                let found = match fs::read_to_string(self.objects_path.join(&row.content_hash)) {
+                    Ok(content) => {
+                        stats.objects += 1;
+                        content.lines()
+                            .enumerate()
+                            .filter(|(_, line)| pattern.is_match(line))
+                            .map(|(i, line)| (i + 1, line.to_string()))
+                            .collect()
+                    }
+                    Err(_) => {
+                        stats.unreadable += 1;
+                        Vec::new()
+                    }
+                };
+                matches_by_hash.insert(row.content_hash.clone(), found);
+            }
+
+            for (line_number, line) in &matches_by_hash[&row.content_hash] {
+                let key = (row.file_path.clone(), line.trim().to_string());
+                let hit = hits.entry(key).or_insert_with(|| GrepHit {
+                    file_path: row.file_path.clone(),
+                    line: line.clone(),
+                    line_number: *line_number,
+                    first_seen: row.timestamp,
+                    first_snapshot: row.id.clone(),
+                    last_seen: row.timestamp,
+                    last_snapshot: row.id.clone(),
+                    snapshots: 0,
+                    still_present: false,
+                });
+                hit.line = line.clone();
+                hit.line_number = *line_number;
+                hit.last_seen = row.timestamp;
+                hit.last_snapshot = row.id.clone();
+                hit.snapshots += 1;
+            }
+        }
+
+        let mut hits: Vec<GrepHit> = hits.into_values().collect();
+        for hit in &mut hits {
+            hit.still_present = newest.get(&hit.file_path) == Some(&hit.last_snapshot);
+        }
+        // Grouped by file, most recently seen files first, lines in file order
+        let mut file_last_seen: HashMap<String, i64> = HashMap::new();
+        for hit in &hits {
+            let seen = file_last_seen.entry(hit.file_path.clone()).or_default();
+            *seen = (*seen).max(hit.last_seen);
+        }
+        hits.sort_by(|a, b| {
+            file_last_seen[&b.file_path].cmp(&file_last_seen[&a.file_path])
+                .then_with(|| a.file_path.cmp(&b.file_path))
+                .then_with(|| a.line_number.cmp(&b.line_number))
+        });
+        Ok((hits, stats))
+    }
+}
@@ -0,0 +1,277 @@
+//! Restores, checkouts, reverts and picks as transactions, with a journal so the last one can be
+//! undone.
+//!
+//! A restore is planned first: which files change, and what each held when the plan was made.
+//! Applying the plan refuses if any of those files has since changed, snapshots and stores the
+//! current content, records the plan in `restore_journal`, and only then writes. If a write
+//! fails, the files already written are put back. `undo` replays a journal entry backwards.
+
+use super::HistoryManager;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use std::fs;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// The files a restore or checkout would rewrite.
+pub struct RestorePlan {
+    pub command: &'static str,
+    pub reference: String,
+    pub files: Vec<PlannedFile>,
+    /// Selected files that already match.
+    pub unchanged: u64,
+}
+
+pub struct PlannedFile {
+    pub file_path: String,
+    /// What the file held when the plan was made; `None` if it didn't exist.
+    pub before_hash: Option<String>,
+    pub after_hash: String,
+}
+
+pub struct UndoOutcome {
+    pub command: String,
+    pub reference: String,
+    pub created_at: i64,
+    pub files: usize,
+    /// The restore or checkout was interrupted, so only the files it reached were put back.
+    pub interrupted: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct JournalEntry {
+    id: String,
+    command: String,
+    reference: String,
+    created_at: i64,
+    status: String,
+}
+
+#[derive(sqlx::FromRow)]
+struct JournalFile {
+    file_path: String,
+    before_hash: Option<String>,
+    after_hash: String,
+}
+
+/// One file rewrite: `new` is written (or the file deleted when `None`); `old` is what goes
+/// back if a later write fails.
+struct FileWrite {
+    path: PathBuf,
+    old: Option<Vec<u8>>,
+    new: Option<Vec<u8>>,
+}
+
+impl RestorePlan {
+    pub fn new(command: &'static str, reference: &str) -> Self {
+        Self { command, reference: reference.to_string(), files: Vec::new(), unchanged: 0 }
+    }
+}
+
+impl HistoryManager {
+    /// Adds `file_path` to the plan unless it already holds `after_hash`.
+    pub(super) fn plan_file(&self, plan: &mut RestorePlan, file_path: &str, after_hash: &str) -> Result<()> {
+        let target = self.base_path.join(file_path);
+        let before_hash = if target.exists() {
+            Some(blake3::hash(&fs::read(&target)?).to_hex().to_string())
+        } else {
+            None
+        };
+        if before_hash.as_deref() == Some(after_hash) {
+            plan.unchanged += 1;
+        } else {
+            plan.files.push(PlannedFile { file_path: file_path.to_string(), before_hash, after_hash: after_hash.to_string() });
+        }
+        Ok(())
+    }
+
+    /// Writes `content` over `file_path` through the journal, so `undo` can reverse it.
+    /// `expected` is what the file held when `content` was worked out from it, `None` if it
+    /// didn't exist; the write is refused if the file no longer matches.
+    pub(super) async fn apply_edit(&self, command: &'static str, reference: &str, file_path: &str, expected: Option<&str>, content: &str) -> Result<()> {
+        self.store_object(content.as_bytes())?;
+        let mut plan = RestorePlan::new(command, reference);
+        plan.files.push(PlannedFile {
+            file_path: file_path.to_string(),
+            before_hash: expected.map(|e| blake3::hash(e.as_bytes()).to_hex().to_string()),
+            after_hash: blake3::hash(content.as_bytes()).to_hex().to_string(),
+        });
+        self.apply_restore(&plan).await
+    }
+
+    /// Writes every planned file or none of them, and journals the change for `undo`.
+    pub async fn apply_restore(&self, plan: &RestorePlan) -> Result<()> {
+        if plan.files.is_empty() {
+            return Ok(());
+        }
+
+        let mut changed = Vec::new();
+        let mut writes = Vec::new();
+        for file in &plan.files {
+            let target = self.base_path.join(&file.file_path);
+            let current = if target.exists() { Some(fs::read(&target)?) } else { None };
+            let current_hash = current.as_ref().map(|c| blake3::hash(c).to_hex().to_string());
+            if current_hash != file.before_hash {
+                changed.push(file.file_path.as_str());
+                continue;
+            }
+            let new = fs::read(self.objects_path.join(&file.after_hash))
+                .context(format!("Failed to read historical object for {}", file.file_path))?;
+            writes.push(FileWrite { path: target, old: current, new: Some(new) });
+        }
+        if !changed.is_empty() {
+            anyhow::bail!("These files changed after the {} was planned; nothing was written:\n  {}", plan.command, changed.join("\n  "));
+        }
+
+        // Safety: snapshot what's about to be overwritten, and keep its exact bytes for undo.
+        // Snapshots only hold text, so other files are kept for undo alone
+        for write in &writes {
+            if let Some(old) = &write.old {
+                if std::str::from_utf8(old).is_ok() {
+                    self.record_change(write.path.clone()).await
+                        .context(format!("Failed to snapshot {} before the {}; nothing was written", write.path.display(), plan.command))?;
+                }
+                self.store_object(old)?;
+            }
+        }
+
+        let journal_id = Uuid::new_v4().to_string();
+        let mut tx = self.db.sqlite.begin().await?;
+        sqlx::query("INSERT INTO restore_journal (id, command, reference, created_at, status) VALUES (?, ?, ?, ?, 'pending')")
+            .bind(&journal_id)
+            .bind(plan.command)
+            .bind(&plan.reference)
+            .bind(Utc::now().timestamp_millis())
+            .execute(&mut *tx)
+            .await?;
+        for file in &plan.files {
+            sqlx::query("INSERT INTO restore_journal_files (journal_id, file_path, before_hash, after_hash) VALUES (?, ?, ?, ?)")
+                .bind(&journal_id)
+                .bind(&file.file_path)
+                .bind(&file.before_hash)
+                .bind(&file.after_hash)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        let result = self.replace_files(&writes);
+        let status = if result.is_ok() { "applied" } else { "rolled_back" };
+        self.set_journal_status(&journal_id, status).await?;
+        result
+    }
+
+    /// Puts back what the most recent restore or checkout overwrote. Refuses if any of those
+    /// files was edited afterwards, since undoing would throw the edits away.
+    pub async fn undo_last_restore(&self) -> Result<UndoOutcome> {
+        let entry = sqlx::query_as::<_, JournalEntry>(
+            "SELECT id, command, reference, created_at, status FROM restore_journal
+             WHERE status IN ('applied', 'pending') ORDER BY created_at DESC LIMIT 1"
+        )
+            .fetch_optional(&self.db.sqlite)
+            .await?
+            .context("Nothing to undo: no restore or checkout has been recorded since the last undo")?;
+        let files = sqlx::query_as::<_, JournalFile>(
+            "SELECT file_path, before_hash, after_hash FROM restore_journal_files WHERE journal_id = ?"
+        )
+            .bind(&entry.id)
+            .fetch_all(&self.db.sqlite)
+            .await?;
+
+        let mut changed = Vec::new();
+        let mut writes = Vec::new();
+        for file in &files {
+            let target = self.base_path.join(&file.file_path);
+            let current = if target.exists() { Some(fs::read(&target)?) } else { None };
+            let current_hash = current.as_ref().map(|c| blake3::hash(c).to_hex().to_string());
+            if current_hash == file.before_hash {
+                // Never written, e.g. an interrupted restore
+                continue;
+            }
+            if current_hash.as_deref() != Some(file.after_hash.as_str()) {
+                changed.push(file.file_path.as_str());
+                continue;
+            }
+            let old = match &file.before_hash {
+                Some(hash) => Some(fs::read(self.objects_path.join(hash))
+                    .context(format!("The pre-{} version of {} is no longer stored", entry.command, file.file_path))?),
+                None => None,
+            };
+            writes.push(FileWrite { path: target, old: current, new: old });
+        }
+        if !changed.is_empty() {
+            anyhow::bail!(
+                "These files were edited after the {} of {}; undoing would discard those edits:\n  {}",
+                entry.command,
+                entry.reference,
+                changed.join("\n  ")
+            );
+        }
+        self.replace_files(&writes)?;
+        self.set_journal_status(&entry.id, "undone").await?;
+        Ok(UndoOutcome {
+            command: entry.command,
+            reference: entry.reference,
+            created_at: entry.created_at,
+            files: writes.len(),
+            interrupted: entry.status == "pending",
+        })
+    }
+
+    /// Applies every write, or on the first failure puts back the ones already made.
+    fn replace_files(&self, writes: &[FileWrite]) -> Result<()> {
+        for (i, write) in writes.iter().enumerate() {
+            if let Err(e) = self.write_or_remove(write.path.clone(), write.new.as_deref()) {
+                let unrestored: Vec<String> = writes[..i].iter()
+                    .filter(|w| self.write_or_remove(w.path.clone(), w.old.as_deref()).is_err())
+                    .map(|w| w.path.display().to_string())
+                    .collect();
+                if !unrestored.is_empty() {
+                    return Err(e.context(format!("Rolling back also failed for: {}", unrestored.join(", "))));
+                }
+                return Err(e.context("All files were put back as they were"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the file through a rename, so it's never left half-written.
+    fn write_or_remove(&self, path: PathBuf, content: Option<&[u8]>) -> Result<()> {
+        let Some(content) = content else {
+            if path.exists() {
+                fs::remove_file(&path).context(format!("Failed to remove {}", path.display()))?;
+            }
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp_dir = self.base_path.join(".stasher").join("tmp");
+        fs::create_dir_all(&tmp_dir)?;
+        let tmp = tmp_dir.join(Uuid::new_v4().to_string());
+        fs::write(&tmp, content).context(format!("Failed to write {}", path.display()))?;
+        if fs::rename(&tmp, &path).is_err() {
+            // Across filesystems a rename can't work; fall back to writing in place
+            let _ = fs::remove_file(&tmp);
+            fs::write(&path, content).context(format!("Failed to write {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    fn store_object(&self, content: &[u8]) -> Result<()> {
+        let object = self.objects_path.join(blake3::hash(content).to_hex().as_str());
+        if !object.exists() {
+            fs::write(object, content)?;
+        }
+        Ok(())
+    }
+
+    async fn set_journal_status(&self, id: &str, status: &str) -> Result<()> {
+        sqlx::query("UPDATE restore_journal SET status = ? WHERE id = ?")
+            .bind(status)
+            .bind(id)
+            .execute(&self.db.sqlite)
+            .await?;
+        Ok(())
+    }
+}
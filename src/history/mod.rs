@@ -6,40 +6,71 @@ use chrono::Utc;
 use uuid::Uuid;
 use similar::{TextDiff, ChangeTag};
 use std::sync::Arc;
-use crate::search::SearchEngine;
+use crate::config::Config;
+use crate::search::{SearchEngine, SnapshotMeta};
+
+mod blame;
+mod compare;
+mod grep;
+mod journal;
+mod notes;
+mod pick;
+mod pickaxe;
+mod reindex;
+mod revert;
+mod sessions;
+mod similarity;
+mod tags;
+mod tree;
+
+pub use compare::{DiffSide, FileChange};
+use journal::RestorePlan;
+pub use notes::{Note, NoteTarget};
+pub use reindex::reindex_running;
+pub use sessions::SessionSummary;
+use sessions::SessionState;
+pub use tags::TagTarget;
+pub use tree::TreeEntry;
 
 pub struct HistoryManager {
     db: Arc<Database>,
     search: Arc<SearchEngine>,
     base_path: PathBuf,
     objects_path: PathBuf,
-    current_session_id: Uuid,
+    session: tokio::sync::Mutex<SessionState>,
 }
 
 impl HistoryManager {
     pub async fn new(db: Arc<Database>, base_path: PathBuf) -> Result<Self> {
-        let objects_path = base_path.join(".stasher").join("objects");
-        
-        // Start a new session for the daemon run
-        let session_id = Uuid::new_v4();
-        let now = Utc::now().timestamp_millis();
-        
-        sqlx::query("INSERT INTO sessions (id, start_time) VALUES (?, ?)")
-            .bind(session_id.to_string())
-            .bind(now)
-            .execute(&db.sqlite)
-            .await?;
-
         // Initialize search engine
-        let search = Arc::new(SearchEngine::new(db.lancedb.clone()).await?);
+        let config = Config::load(&base_path)?;
+        let search = SearchEngine::new(&db, &config).await?;
+        Ok(Self::with_search(db, base_path, search))
+    }
 
-        Ok(Self {
+    /// Opens the history without loading an embedding model, for commands that only read it
+    /// (grep, pickaxe, blame). Anything recorded through it is indexed for keywords only.
+    pub async fn without_model(db: Arc<Database>, base_path: PathBuf) -> Result<Self> {
+        let config = Config::load(&base_path)?;
+        let index = crate::search::active_index(&db).await?;
+        let search = SearchEngine::with_embedder(&db, crate::search::embedder::model_id(&config), None, index)?;
+        Ok(Self::with_search(db, base_path, search))
+    }
+
+    fn with_search(db: Arc<Database>, base_path: PathBuf, search: SearchEngine) -> Self {
+        let objects_path = base_path.join(".stasher").join("objects");
+        let search = Arc::new(search);
+        Self {
             db,
             search,
             base_path,
             objects_path,
-            current_session_id: session_id,
-        })
+            session: tokio::sync::Mutex::new(SessionState::default()),
+        }
+    }
+
+    pub fn search_engine(&self) -> &SearchEngine {
+        &self.search
     }
 
     pub async fn record_change(&self, file_path: PathBuf) -> Result<()> {
@@ -61,6 +92,7 @@ impl HistoryManager {
         .fetch_optional(&self.db.sqlite)
         .await?;
 
+        let is_new_file = latest.is_none();
         let (diff_patch, added, removed) = if let Some((old_hash, _)) = latest {
             if old_hash == new_hash {
                 // No actual content change
@@ -101,10 +133,11 @@ impl HistoryManager {
             (patch, content.lines().count() as i32, 0)
         };
 
-        let snapshot_id = self.save_snapshot(&relative_path, &new_hash, &diff_patch, added, removed).await?;
+        let meta = self.save_snapshot(&relative_path, &new_hash, &diff_patch, added, removed).await?;
 
-        // 3. Index for semantic search
-        if let Err(e) = self.search.index_snapshot(snapshot_id, relative_path, content.clone()).await {
+        // 3. Index for semantic search. A new file's patch is the whole file, already covered by its content.
+        let patch = (!is_new_file).then_some(diff_patch.as_str());
+        if let Err(e) = self.search.index_snapshot(meta, content.clone(), patch).await {
             eprintln!("⚠️ Failed to index snapshot: {}", e);
         }
 
@@ -115,16 +148,17 @@ impl HistoryManager {
         Ok(())
     }
 
-    async fn save_snapshot(&self, file_path: &str, hash: &str, patch: &str, added: i32, removed: i32) -> Result<String> {
+    async fn save_snapshot(&self, file_path: &str, hash: &str, patch: &str, added: i32, removed: i32) -> Result<SnapshotMeta> {
         let snapshot_id = Uuid::new_v4().to_string();
         let now = Utc::now().timestamp_millis();
+        let session_id = self.session_for_write(now).await?;
 
         sqlx::query(
             "INSERT INTO snapshots (id, session_id, file_path, timestamp, diff_patch, content_hash, lines_added, lines_removed) 
              VALUES (?, ?, ?, ?, ?, ?, ?, ?)"
         )
         .bind(&snapshot_id)
-        .bind(self.current_session_id.to_string())
+        .bind(session_id)
         .bind(file_path)
         .bind(now)
         .bind(patch)
@@ -134,18 +168,101 @@ impl HistoryManager {
         .execute(&self.db.sqlite)
         .await?;
 
-        Ok(snapshot_id)
+        Ok(SnapshotMeta {
+            snapshot_id,
+            file_path: file_path.to_string(),
+            content_hash: hash.to_string(),
+        })
+    }
+
+    /// Records that a tracked file was deleted or moved away, so project states after this
+    /// moment no longer contain it. Untracked paths and files already recorded as gone are
+    /// left alone.
+    pub async fn record_deletion(&self, file_path: &Path) -> Result<()> {
+        if file_path.exists() || self.is_internal_path(file_path) {
+            return Ok(());
+        }
+        let relative_path = file_path.strip_prefix(&self.base_path)
+            .unwrap_or(file_path)
+            .to_string_lossy()
+            .to_string();
+
+        let present: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM snapshots s WHERE s.file_path = ?
+             AND s.timestamp >= COALESCE((SELECT MAX(timestamp) FROM file_deletions WHERE file_path = s.file_path), 0))"
+        )
+        .bind(&relative_path)
+        .fetch_one(&self.db.sqlite)
+        .await?;
+        if !present {
+            return Ok(());
+        }
+
+        let now = Utc::now().timestamp_millis();
+        let session_id = self.session_for_write(now).await?;
+        sqlx::query("INSERT INTO file_deletions (id, session_id, file_path, timestamp) VALUES (?, ?, ?, ?)")
+            .bind(Uuid::new_v4().to_string())
+            .bind(session_id)
+            .bind(&relative_path)
+            .bind(now)
+            .execute(&self.db.sqlite)
+            .await?;
+        Ok(())
+    }
+
+    /// Records every tracked file that is no longer on disk, for deletions made while the
+    /// daemon wasn't running.
+    pub async fn record_missing_files(&self) -> Result<()> {
+        let paths: Vec<String> = sqlx::query_scalar("SELECT DISTINCT file_path FROM snapshots")
+            .fetch_all(&self.db.sqlite)
+            .await?;
+        for path in paths {
+            self.record_deletion(&self.base_path.join(path)).await?;
+        }
+        Ok(())
     }
 
     pub async fn prune_history(&self, days: u32) -> Result<(u64, u64)> {
         let cutoff = Utc::now().timestamp_millis() - (days as i64 * 24 * 60 * 60 * 1000);
         
-        // 1. Delete old snapshots from SQLite
-        let rows_affected = sqlx::query("DELETE FROM snapshots WHERE timestamp < ?")
+        // 1. Delete old snapshots from SQLite, keeping anything a tag points at
+        let rows_affected = sqlx::query(
+            "DELETE FROM snapshots WHERE timestamp < ? AND id NOT IN (SELECT snapshot_id FROM tag_snapshots)"
+        )
             .bind(cutoff)
             .execute(&self.db.sqlite)
             .await?
             .rows_affected();
+        let orphaned: Vec<String> = sqlx::query_scalar(
+            "SELECT DISTINCT key FROM chunks_fts WHERE CASE kind
+                WHEN 'content' THEN key NOT IN (SELECT content_hash FROM snapshots)
+                ELSE key NOT IN (SELECT id FROM snapshots)
+             END"
+        )
+            .fetch_all(&self.db.sqlite)
+            .await?;
+        self.search.remove_vectors(&orphaned).await?;
+        sqlx::query(
+            "DELETE FROM chunks_fts WHERE CASE kind
+                WHEN 'content' THEN key NOT IN (SELECT content_hash FROM snapshots)
+                ELSE key NOT IN (SELECT id FROM snapshots)
+             END"
+        )
+            .execute(&self.db.sqlite)
+            .await?;
+        sqlx::query("DELETE FROM indexed_content WHERE content_hash NOT IN (SELECT content_hash FROM snapshots)")
+            .execute(&self.db.sqlite)
+            .await?;
+
+        // Restores from before the cutoff can no longer be undone
+        sqlx::query("DELETE FROM restore_journal_files WHERE journal_id IN (SELECT id FROM restore_journal WHERE created_at < ?)")
+            .bind(cutoff)
+            .execute(&self.db.sqlite)
+            .await?;
+        sqlx::query("DELETE FROM restore_journal WHERE created_at < ?")
+            .bind(cutoff)
+            .execute(&self.db.sqlite)
+            .await?;
 
         // 2. Perform Garbage Collection on the objects folder
         let deleted_objects = self.cleanup_unused_objects().await?;
@@ -156,8 +273,13 @@ impl HistoryManager {
     async fn cleanup_unused_objects(&self) -> Result<u64> {
         use std::collections::HashSet;
         
-        // Find all hashes still referenced in the database
-        let active_hashes: Vec<(String,)> = sqlx::query_as("SELECT DISTINCT content_hash FROM snapshots")
+        // Find all hashes still referenced in the database, including what restores
+        // overwrote so they can still be undone
+        let active_hashes: Vec<(String,)> = sqlx::query_as(
+            "SELECT content_hash FROM snapshots
+             UNION SELECT f.before_hash FROM restore_journal_files f JOIN restore_journal j ON j.id = f.journal_id
+             WHERE f.before_hash IS NOT NULL AND j.status IN ('applied', 'pending')"
+        )
             .fetch_all(&self.db.sqlite)
             .await?;
         
@@ -234,22 +356,34 @@ impl HistoryManager {
         
         // Count files indexed in LanceDB
         let table_names: Vec<String> = self.db.lancedb.table_names().execute().await?;
-        let indexed_count = if table_names.contains(&"snapshots_v1".to_string()) {
-            let table = self.db.lancedb.open_table("snapshots_v1").execute().await?;
+        let chunks_table = self.search.index().chunks_table();
+        let indexed_count = if table_names.contains(&chunks_table) {
+            let table = self.db.lancedb.open_table(&chunks_table).execute().await?;
             table.count_rows(None).await? as u64
         } else {
             0
         };
 
+        let threshold = Config::load(&self.base_path)?.vector_index_threshold;
         Ok(ProjectStats {
+            vector_index: self.search.vector_index_status(threshold).await?,
             total_snapshots,
             total_sessions,
             objects_size,
             total_size: db_size,
             indexed_count,
+            semantic_search: self.search.has_model(),
+            vectors_incomplete: crate::search::vectors_incomplete(&self.db.sqlite).await?
+                || !crate::search::legacy_tables(&self.db.lancedb).await?.is_empty(),
         })
     }
 
+    /// Keeps the approximate vector index in step with the history. See `search::ann`.
+    pub async fn maintain_vector_index(&self) -> Result<crate::search::ann::Maintenance> {
+        let threshold = Config::load(&self.base_path)?.vector_index_threshold;
+        self.search.maintain_vector_index(threshold).await
+    }
+
     fn calculate_dir_size(&self, path: &Path) -> Result<u64> {
         let mut size = 0;
         if path.exists() {
@@ -266,13 +400,13 @@ impl HistoryManager {
         Ok(size)
     }
 
-    pub async fn get_snapshot_diff(&self, snapshot_id: &str) -> Result<String> {
-        let diff: String = sqlx::query_scalar("SELECT diff_patch FROM snapshots WHERE id = ? OR id LIKE ?")
-            .bind(snapshot_id)
-            .bind(format!("{}%", snapshot_id))
+    pub async fn get_snapshot_diff(&self, reference: &str) -> Result<String> {
+        let snapshot_id = self.resolve_snapshot(reference, None).await?;
+        let diff: String = sqlx::query_scalar("SELECT diff_patch FROM snapshots WHERE id = ?")
+            .bind(&snapshot_id)
             .fetch_one(&self.db.sqlite)
             .await
-            .context(format!("Snapshot {} not found", snapshot_id))?;
+            .context(format!("Snapshot {} not found", reference))?;
             
         Ok(diff)
     }
@@ -290,15 +424,16 @@ impl HistoryManager {
             .to_string()
     }
 
-    pub async fn restore_file(&self, file_path: &str, snapshot_id: Option<String>) -> Result<()> {
+    /// Plans restoring `file_path` to `snapshot`, or to its latest recorded version.
+    pub async fn plan_restore(&self, file_path: &str, snapshot: Option<String>) -> Result<RestorePlan> {
         let rel_path = self.to_stasher_relative(file_path);
 
-        let (hash, actual_path): (String, String) = if let Some(id) = snapshot_id {
+        let (hash, actual_path): (String, String) = if let Some(reference) = &snapshot {
+            let id = self.resolve_snapshot(reference, Some(file_path)).await?;
             sqlx::query_as("SELECT content_hash, file_path FROM snapshots WHERE id = ?")
                 .bind(&id)
-                .fetch_optional(&self.db.sqlite)
+                .fetch_one(&self.db.sqlite)
                 .await?
-                .context(format!("Snapshot ID {} not found", id))?
         } else {
             // Restore to the latest known state for this file
             sqlx::query_as("SELECT content_hash, file_path FROM snapshots WHERE file_path = ? OR file_path LIKE ? ORDER BY timestamp DESC LIMIT 1")
@@ -309,19 +444,9 @@ impl HistoryManager {
                 .context(format!("No history found for file: {}", file_path))?
         };
 
-        let object_path = self.objects_path.join(&hash);
-        let content = fs::read(object_path)
-            .context("Failed to read historical object from CAS")?;
-
-        let target_path = if PathBuf::from(&actual_path).is_absolute() {
-            PathBuf::from(&actual_path)
-        } else {
-            self.base_path.join(&actual_path)
-        };
-        fs::write(target_path, content)
-            .context("Failed to write restored content to disk")?;
-
-        Ok(())
+        let mut plan = RestorePlan::new("restore", snapshot.as_deref().unwrap_or("latest"));
+        self.plan_file(&mut plan, &actual_path, &hash)?;
+        Ok(plan)
     }
 
     pub async fn list_snapshots(&self, file_path: &str) -> Result<Vec<SnapshotSummary>> {
@@ -373,7 +498,52 @@ impl HistoryManager {
     }
 }
 
-#[derive(sqlx::FromRow)]
+/// Parses a point in time given as epoch milliseconds, `YYYY-MM-DD`, RFC 3339,
+/// `now`, `today`, `yesterday`, or an age such as `45m`, `3d` or `2w`.
+pub fn parse_time_spec(spec: &str) -> Result<i64> {
+    use chrono::{DateTime, Local, NaiveDate};
+
+    let spec = spec.trim();
+    let start_of = |date: NaiveDate| -> Result<i64> {
+        date.and_hms_opt(0, 0, 0)
+            .and_then(|dt| dt.and_local_timezone(Local).earliest())
+            .map(|dt| dt.timestamp_millis())
+            .context(format!("Invalid date: {}", spec))
+    };
+
+    match spec {
+        "now" => return Ok(Utc::now().timestamp_millis()),
+        "today" => return start_of(Local::now().date_naive()),
+        "yesterday" => return start_of(Local::now().date_naive() - chrono::Duration::days(1)),
+        _ => {}
+    }
+
+    if let Ok(millis) = spec.parse::<i64>() {
+        return Ok(millis);
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(spec) {
+        return Ok(dt.timestamp_millis());
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(spec, "%Y-%m-%d") {
+        return start_of(date);
+    }
+
+    let unit_ms: i64 = match spec.chars().last() {
+        Some('s') => 1000,
+        Some('m') => 60 * 1000,
+        Some('h') => 60 * 60 * 1000,
+        Some('d') => 24 * 60 * 60 * 1000,
+        Some('w') => 7 * 24 * 60 * 60 * 1000,
+        _ => anyhow::bail!("Unrecognized time: {}", spec),
+    };
+    let amount: i64 = spec[..spec.len() - 1]
+        .parse()
+        .context(format!("Unrecognized time: {}", spec))?;
+
+    Ok(Utc::now().timestamp_millis() - amount * unit_ms)
+}
+
+#[derive(serde::Serialize, sqlx::FromRow)]
 pub struct SnapshotSummary {
     pub id: String,
     pub timestamp: i64,
@@ -389,4 +559,58 @@ pub struct ProjectStats {
     pub objects_size: u64,
     pub total_size: u64,
     pub indexed_count: u64,
+    pub semantic_search: bool,
+    /// Some history has no vectors in the live index: it was indexed without a model, or its
+    /// vectors are in tables from an older index format.
+    pub vectors_incomplete: bool,
+    /// `None` until the first vector is written.
+    pub vector_index: Option<crate::search::ann::VectorIndexStatus>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_time_spec;
+    use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+
+    fn close_to(actual: i64, expected: i64) -> bool {
+        (actual - expected).abs() < 5_000
+    }
+
+    #[test]
+    fn parses_ages() {
+        let now = Utc::now().timestamp_millis();
+        assert!(close_to(parse_time_spec("now").unwrap(), now));
+        assert!(close_to(parse_time_spec("45m").unwrap(), now - 45 * 60_000));
+        assert!(close_to(parse_time_spec("1d").unwrap(), now - 86_400_000));
+        assert!(close_to(parse_time_spec(" 2w ").unwrap(), now - 14 * 86_400_000));
+    }
+
+    #[test]
+    fn parses_dates_and_instants() {
+        let may_first = Local
+            .from_local_datetime(&NaiveDate::from_ymd_opt(2024, 5, 1).unwrap().and_hms_opt(0, 0, 0).unwrap())
+            .earliest()
+            .unwrap()
+            .timestamp_millis();
+        assert_eq!(parse_time_spec("2024-05-01").unwrap(), may_first);
+
+        let instant = DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z").unwrap().timestamp_millis();
+        assert_eq!(parse_time_spec("2024-05-01T10:00:00Z").unwrap(), instant);
+        assert_eq!(parse_time_spec("1714557600000").unwrap(), 1714557600000);
+    }
+
+    #[test]
+    fn yesterday_is_a_day_before_today() {
+        let today = parse_time_spec("today").unwrap();
+        let yesterday = parse_time_spec("yesterday").unwrap();
+        // A day can be 23 or 25 hours across a DST change
+        assert!((yesterday - (today - 86_400_000)).abs() <= 3_600_000);
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        for spec in ["", "d", "3x", "abcd", "yesterdayish", "1.5d"] {
+            assert!(parse_time_spec(spec).is_err(), "{spec:?} should not parse");
+        }
+    }
 }
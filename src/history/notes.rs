@@ -0,0 +1,172 @@
+use super::HistoryManager;
+use crate::db::fts5_query;
+use anyhow::Result;
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum NoteTarget {
+    Snapshot,
+    Session,
+}
+
+impl NoteTarget {
+    fn as_str(self) -> &'static str {
+        match self {
+            NoteTarget::Snapshot => "snapshot",
+            NoteTarget::Session => "session",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, sqlx::FromRow)]
+pub struct Note {
+    pub id: String,
+    pub target_kind: String,
+    pub target_id: String,
+    pub body: String,
+    pub created_at: i64,
+}
+
+impl HistoryManager {
+    /// Attaches a markdown note to a snapshot or session. Without an explicit `kind`, the target
+    /// must resolve to exactly one of a snapshot reference or a session id.
+    pub async fn add_note(&self, target: &str, kind: Option<NoteTarget>, body: &str) -> Result<Note> {
+        if body.trim().is_empty() {
+            anyhow::bail!("Note is empty");
+        }
+
+        let (kind, target_id) = match kind {
+            Some(NoteTarget::Snapshot) => (NoteTarget::Snapshot, self.resolve_snapshot(target, None).await?),
+            Some(NoteTarget::Session) => (NoteTarget::Session, self.get_session(target).await?.id),
+            None => match (self.resolve_snapshot(target, None).await, self.get_session(target).await) {
+                (Ok(snapshot_id), Ok(session)) => anyhow::bail!(
+                    "{} matches both snapshot {} and session {}; pass --snapshot or --session",
+                    target,
+                    &snapshot_id[..7],
+                    &session.id[..7]
+                ),
+                (Ok(snapshot_id), Err(_)) => (NoteTarget::Snapshot, snapshot_id),
+                (Err(_), Ok(session)) => (NoteTarget::Session, session.id),
+                (Err(e), Err(_)) => anyhow::bail!("{} is neither a snapshot nor a session: {}", target, e),
+            },
+        };
+
+        let note = Note {
+            id: Uuid::new_v4().to_string(),
+            target_kind: kind.as_str().to_string(),
+            target_id,
+            body: body.to_string(),
+            created_at: Utc::now().timestamp_millis(),
+        };
+
+        let mut tx = self.db.sqlite.begin().await?;
+        sqlx::query("INSERT INTO notes (id, target_kind, target_id, body, created_at) VALUES (?, ?, ?, ?, ?)")
+            .bind(&note.id)
+            .bind(&note.target_kind)
+            .bind(&note.target_id)
+            .bind(&note.body)
+            .bind(note.created_at)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("INSERT INTO notes_fts (note_id, body) VALUES (?, ?)")
+            .bind(&note.id)
+            .bind(&note.body)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        if let Err(e) = self.search.index_note(&note.id, &note.body).await {
+            eprintln!("⚠️ Failed to index note: {}", e);
+        }
+
+        Ok(note)
+    }
+
+    /// Removes a note by id or unique prefix.
+    pub async fn delete_note(&self, note_id: &str) -> Result<()> {
+        let mut ids: Vec<String> = sqlx::query_scalar("SELECT id FROM notes WHERE id = ? OR id LIKE ? LIMIT 2")
+            .bind(note_id)
+            .bind(format!("{}%", note_id))
+            .fetch_all(&self.db.sqlite)
+            .await?;
+        let id = match ids.len() {
+            0 => anyhow::bail!("Note {} not found", note_id),
+            1 => ids.remove(0),
+            _ => anyhow::bail!("Note prefix {} is ambiguous", note_id),
+        };
+
+        let mut tx = self.db.sqlite.begin().await?;
+        sqlx::query("DELETE FROM notes WHERE id = ?").bind(&id).execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM notes_fts WHERE note_id = ?").bind(&id).execute(&mut *tx).await?;
+        tx.commit().await?;
+
+        self.search.remove_note(&id).await?;
+        Ok(())
+    }
+
+    /// Notes attached to the given snapshots or sessions, grouped by target id, oldest first.
+    pub async fn notes_for(&self, kind: NoteTarget, target_ids: &[String]) -> Result<HashMap<String, Vec<Note>>> {
+        let rows = sqlx::query_as::<_, Note>(
+            "SELECT id, target_kind, target_id, body, created_at FROM notes
+             WHERE target_kind = ? AND target_id IN (SELECT value FROM json_each(?))
+             ORDER BY created_at ASC"
+        )
+        .bind(kind.as_str())
+        .bind(serde_json::to_string(target_ids)?)
+        .fetch_all(&self.db.sqlite)
+        .await?;
+
+        let mut notes: HashMap<String, Vec<Note>> = HashMap::new();
+        for note in rows {
+            notes.entry(note.target_id.clone()).or_default().push(note);
+        }
+        Ok(notes)
+    }
+
+    /// Finds notes by keyword and by meaning, merging both rankings.
+    pub async fn search_notes(&self, query: &str, limit: usize) -> Result<Vec<Note>> {
+        let fts_query = fts5_query(query);
+        let lexical: Vec<String> = if fts_query.is_empty() {
+            Vec::new()
+        } else {
+            sqlx::query_scalar("SELECT note_id FROM notes_fts WHERE notes_fts MATCH ? ORDER BY bm25(notes_fts) LIMIT ?")
+                .bind(&fts_query)
+                .bind(limit as i64)
+                .fetch_all(&self.db.sqlite)
+                .await?
+        };
+        let semantic = match self.search.search_notes(query, limit).await {
+            Ok(ids) => ids,
+            Err(e) => {
+                eprintln!("⚠️ Semantic note search failed: {}", e);
+                Vec::new()
+            }
+        };
+
+        // Reciprocal rank fusion: notes ranked well by either method float to the top
+        let mut scores: HashMap<String, f64> = HashMap::new();
+        for ranking in [&lexical, &semantic] {
+            for (rank, id) in ranking.iter().enumerate() {
+                *scores.entry(id.clone()).or_default() += 1.0 / (60.0 + rank as f64);
+            }
+        }
+        let mut ranked: Vec<(String, f64)> = scores.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked.truncate(limit);
+
+        let mut notes = Vec::new();
+        for (id, _) in ranked {
+            let note = sqlx::query_as::<_, Note>(
+                "SELECT id, target_kind, target_id, body, created_at FROM notes WHERE id = ?"
+            )
+            .bind(&id)
+            .fetch_optional(&self.db.sqlite)
+            .await?;
+            notes.extend(note);
+        }
+        Ok(notes)
+    }
+}
@@ -0,0 +1,136 @@
+//! Bringing back parts of an old version, hunk by hunk.
+
+use super::HistoryManager;
+use anyhow::{Context, Result};
+use similar::{capture_diff_slices, group_diff_ops, Algorithm, DiffOp, DiffTag};
+use std::fs;
+
+/// Lines of unchanged context shown around each hunk.
+const CONTEXT_LINES: usize = 3;
+
+/// The hunks between the working file and a historical version of it.
+pub struct Pick {
+    /// The snapshot the hunks come from, as it was given.
+    pub reference: String,
+    pub file_path: String,
+    /// The working file when the pick was planned; empty if it doesn't exist.
+    pub working: String,
+    pub historical: String,
+    /// Each hunk's ops, over the lines of `working` (old) and `historical` (new).
+    pub hunks: Vec<Vec<DiffOp>>,
+}
+
+impl Pick {
+    pub fn working_lines(&self) -> Vec<&str> {
+        self.working.split_inclusive('\n').collect()
+    }
+
+    pub fn historical_lines(&self) -> Vec<&str> {
+        self.historical.split_inclusive('\n').collect()
+    }
+
+    /// The working file with the `accepted` hunks taken from the historical version.
+    pub fn apply(&self, accepted: &[bool]) -> String {
+        let working = self.working_lines();
+        let historical = self.historical_lines();
+        let mut out = String::new();
+        let mut pos = 0;
+        for (hunk, _) in self.hunks.iter().zip(accepted).filter(|(_, accepted)| **accepted) {
+            for op in hunk.iter().filter(|op| op.tag() != DiffTag::Equal) {
+                out.push_str(&working[pos..op.old_range().start].concat());
+                out.push_str(&historical[op.new_range()].concat());
+                pos = op.old_range().end;
+            }
+        }
+        out.push_str(&working[pos..].concat());
+        out
+    }
+}
+
+impl HistoryManager {
+    /// Diffs the working file against the version `reference` saved, read from the object store.
+    pub async fn plan_pick(&self, reference: &str) -> Result<Pick> {
+        let id = self.resolve_snapshot(reference, None).await?;
+        let (file_path, content_hash): (String, String) =
+            sqlx::query_as("SELECT file_path, content_hash FROM snapshots WHERE id = ?")
+                .bind(&id)
+                .fetch_one(&self.db.sqlite)
+                .await?;
+        let historical = fs::read_to_string(self.objects_path.join(&content_hash))
+            .context(format!("The version of {} saved by this snapshot is no longer stored", file_path))?;
+        let target = self.base_path.join(&file_path);
+        let working = if target.exists() {
+            fs::read_to_string(&target).context(format!("Failed to read {}", file_path))?
+        } else {
+            String::new()
+        };
+
+        let working_lines: Vec<&str> = working.split_inclusive('\n').collect();
+        let historical_lines: Vec<&str> = historical.split_inclusive('\n').collect();
+        let ops = capture_diff_slices(Algorithm::Myers, &working_lines, &historical_lines);
+        let hunks = group_diff_ops(ops, CONTEXT_LINES);
+        Ok(Pick { reference: reference.to_string(), file_path, working, historical, hunks })
+    }
+
+    /// Writes the working file with the accepted hunks applied, after snapshotting it, and
+    /// journals the write for `undo`. Refuses if the file changed since the pick was planned.
+    pub async fn apply_pick(&self, pick: &Pick, accepted: &[bool]) -> Result<()> {
+        let target = self.base_path.join(&pick.file_path);
+        let expected = target.exists().then_some(pick.working.as_str());
+        self.apply_edit("pick", &pick.reference, &pick.file_path, expected, &pick.apply(accepted)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Pick, CONTEXT_LINES};
+    use similar::{capture_diff_slices, group_diff_ops, Algorithm};
+
+    fn pick(working: &str, historical: &str) -> Pick {
+        let working_lines: Vec<&str> = working.split_inclusive('\n').collect();
+        let historical_lines: Vec<&str> = historical.split_inclusive('\n').collect();
+        let ops = capture_diff_slices(Algorithm::Myers, &working_lines, &historical_lines);
+        Pick {
+            reference: "1".to_string(),
+            file_path: "a.txt".to_string(),
+            working: working.to_string(),
+            historical: historical.to_string(),
+            hunks: group_diff_ops(ops, CONTEXT_LINES),
+        }
+    }
+
+    fn numbered(replace: &[(usize, &str)]) -> String {
+        (1..=12)
+            .map(|n| match replace.iter().find(|(line, _)| *line == n) {
+                Some((_, text)) => format!("{}\n", text),
+                None => format!("{}\n", n),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn applies_only_accepted_hunks() {
+        let working = numbered(&[(2, "two"), (11, "eleven")]);
+        let historical = numbered(&[]);
+        let pick = pick(&working, &historical);
+        assert_eq!(pick.hunks.len(), 2);
+
+        assert_eq!(pick.apply(&[true, true]), historical);
+        assert_eq!(pick.apply(&[false, false]), working);
+        assert_eq!(pick.apply(&[true, false]), numbered(&[(11, "eleven")]));
+        assert_eq!(pick.apply(&[false, true]), numbered(&[(2, "two")]));
+    }
+
+    #[test]
+    fn applies_insertions_and_deletions() {
+        let pick = pick("a\nb\nc\n", "a\nc\nd\n");
+        assert_eq!(pick.apply(&[true]), "a\nc\nd\n");
+    }
+
+    #[test]
+    fn recreates_a_missing_file() {
+        let pick = pick("", "fn main() {}\n");
+        assert_eq!(pick.apply(&[true]), "fn main() {}\n");
+        assert_eq!(pick.apply(&[false]), "");
+    }
+}
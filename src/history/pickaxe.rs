@@ -0,0 +1,164 @@
+use super::HistoryManager;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+
+/// A snapshot that changed how many times the text occurs in its file.
+pub struct PickaxeChange {
+    pub snapshot_id: String,
+    pub file_path: String,
+    pub timestamp: i64,
+    pub before: usize,
+    pub after: usize,
+    /// The hunks of the snapshot's patch that add or remove the text.
+    pub hunks: Vec<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct PickaxeRow {
+    id: String,
+    file_path: String,
+    timestamp: i64,
+    content_hash: String,
+    diff_patch: String,
+}
+
+impl HistoryManager {
+    /// Like `git log -S`: walks each file's versions oldest first and reports every snapshot
+    /// where the number of occurrences of `text` changed. With `file_path`, only that file's
+    /// lineage is walked, following it back through moves.
+    pub async fn pickaxe(&self, text: &str, file_path: Option<&str>) -> Result<Vec<PickaxeChange>> {
+        let rows: Vec<PickaxeRow> = match file_path {
+            Some(file_path) => {
+                let ids: Vec<String> = self.list_snapshots(file_path).await?.into_iter().map(|s| s.id).collect();
+                // A moved file is one lineage, so don't group by path
+                sqlx::query_as(
+                    "SELECT id, file_path, timestamp, content_hash, diff_patch FROM snapshots
+                     WHERE id IN (SELECT value FROM json_each(?))
+                     ORDER BY timestamp"
+                )
+                .bind(serde_json::to_string(&ids)?)
+                .fetch_all(&self.db.sqlite)
+                .await?
+            }
+            None => sqlx::query_as("SELECT id, file_path, timestamp, content_hash, diff_patch FROM snapshots ORDER BY file_path, timestamp")
+                .fetch_all(&self.db.sqlite)
+                .await?,
+        };
+
+        let mut counts: HashMap<String, Option<usize>> = HashMap::new();
+        let mut previous: Option<(String, usize)> = None;
+        let mut changes = Vec::new();
+        for row in rows {
+            let count = *counts.entry(row.content_hash.clone()).or_insert_with(|| {
+                fs::read_to_string(self.objects_path.join(&row.content_hash))
+                    .ok()
+                    .map(|content| content.matches(text).count())
+            });
+            // Like blame, skip versions whose object is missing or not text rather than
+            // reporting the text as gone and back again
+            let Some(count) = count else {
+                continue;
+            };
+
+            let before = match &previous {
+                Some((path, before)) if file_path.is_some() || *path == row.file_path => *before,
+                _ => 0,
+            };
+            if count != before {
+                changes.push(PickaxeChange {
+                    hunks: hunks_touching(&row.diff_patch, text),
+                    snapshot_id: row.id,
+                    file_path: row.file_path.clone(),
+                    timestamp: row.timestamp,
+                    before,
+                    after: count,
+                });
+            }
+            previous = Some((row.file_path, count));
+        }
+
+        changes.sort_by_key(|c| c.timestamp);
+        Ok(changes)
+    }
+}
+
+/// Lines of context kept around a match when a hunk is trimmed.
+const CONTEXT_LINES: usize = 3;
+
+/// Splits a unified diff into hunks and keeps those whose added or removed lines contain `text`,
+/// trimmed to the lines around those matches. A new file's first snapshot is one hunk holding
+/// the whole file, which would otherwise bury the match.
+fn hunks_touching(patch: &str, text: &str) -> Vec<String> {
+    let mut hunks: Vec<(&str, Vec<&str>)> = Vec::new();
+    for line in patch.lines() {
+        if line.starts_with("@@") {
+            hunks.push((line, Vec::new()));
+        } else if let Some((_, lines)) = hunks.last_mut() {
+            lines.push(line);
+        }
+    }
+
+    hunks
+        .into_iter()
+        .filter_map(|(header, lines)| {
+            let matches: Vec<usize> = lines.iter()
+                .enumerate()
+                .filter(|(_, line)| (line.starts_with('+') || line.starts_with('-')) && line.contains(text))
+                .map(|(i, _)| i)
+                .collect();
+            if matches.is_empty() {
+                return None;
+            }
+            let mut hunk = format!("{}\n", header);
+            let mut shown_until = 0;
+            for i in matches {
+                let start = i.saturating_sub(CONTEXT_LINES).max(shown_until);
+                let end = (i + CONTEXT_LINES + 1).min(lines.len());
+                if start > shown_until {
+                    hunk.push_str(" ...\n");
+                }
+                for line in &lines[start..end] {
+                    hunk.push_str(line);
+                    hunk.push('\n');
+                }
+                shown_until = shown_until.max(end);
+            }
+            if shown_until < lines.len() {
+                hunk.push_str(" ...\n");
+            }
+            Some(hunk)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hunks_touching;
+
+    #[test]
+    fn keeps_only_hunks_that_change_the_text() {
+        let patch = "--- a.rs\n+++ a.rs\n@@ -1,2 +1,2 @@\n-fn a() {}\n+fn b() {}\n@@ -10,2 +10,2 @@\n fn needle() {}\n-let x = needle();\n+let x = 1;\n";
+        assert_eq!(hunks_touching(patch, "needle"), vec![
+            "@@ -10,2 +10,2 @@\n fn needle() {}\n-let x = needle();\n+let x = 1;\n".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn ignores_matches_in_context_lines() {
+        let patch = "@@ -1,3 +1,3 @@\n needle\n-a\n+b\n";
+        assert!(hunks_touching(patch, "needle").is_empty());
+    }
+
+    #[test]
+    fn trims_long_hunks_around_the_match() {
+        let mut patch = String::from("@@ -0,0 +1,20 @@\n");
+        for n in 0..20 {
+            patch.push_str(&if n == 10 { "+needle\n".to_string() } else { format!("+line {}\n", n) });
+        }
+        assert_eq!(
+            hunks_touching(&patch, "needle"),
+            vec!["@@ -0,0 +1,20 @@\n ...\n+line 7\n+line 8\n+line 9\n+needle\n+line 11\n+line 12\n+line 13\n ...\n".to_string()],
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use super::HistoryManager;
+use crate::config::Config;
+use crate::search::{self, IndexInfo, SearchEngine, SearchRecord, SnapshotMeta};
+use anyhow::Result;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const LOCK_FILE: &str = ".stasher/reindex.lock";
+
+#[derive(Default)]
+pub struct ReindexStats {
+    pub contents: u64,
+    pub changes: u64,
+    pub notes: u64,
+    pub chunks: u64,
+    pub missing_objects: u64,
+}
+
+impl HistoryManager {
+    /// Rebuilds every vector from the CAS objects and recorded patches into a fresh generation of
+    /// tables, optionally with a different model, then switches over in one transaction. Searches
+    /// use the old tables until the switch, and a failed rebuild leaves them untouched.
+    ///
+    /// Refuses to run alongside the daemon. Snapshots and notes that other commands record
+    /// meanwhile are caught up before the switch.
+    pub async fn reindex(&self, model: Option<&str>) -> Result<ReindexStats> {
+        let _lock = ReindexLock::acquire(&self.base_path)?;
+        if let Some(pid) = crate::daemon::running_pid(&self.base_path) {
+            anyhow::bail!("The daemon (PID {}) is recording into the current index. Stop it, reindex, then start it again.", pid);
+        }
+
+        let mut config = Config::load(&self.base_path)?;
+        if let Some(model) = model {
+            config.embedding_model = model.to_string();
+        }
+
+        let old_index = self.search.index();
+        let generation = next_generation(&old_index.generation);
+        let new_index = IndexInfo { generation: generation.clone(), model: None };
+        // Leftovers from an interrupted reindex
+        drop_tables(&self.db.lancedb, &new_index).await;
+        let engine = SearchEngine::with_index(&self.db, &config, new_index).await?;
+        if !engine.has_model() {
+            anyhow::bail!("Cannot rebuild the index without an embedding model");
+        }
+
+        let mut stats = ReindexStats::default();
+        let mut lexical: Vec<SearchRecord> = Vec::new();
+        // Anything past these rows was recorded during the rebuild and is caught up below
+        let mut cursor: Cursor = sqlx::query_as(
+            "SELECT (SELECT COALESCE(MAX(rowid), 0) FROM snapshots), (SELECT COALESCE(MAX(rowid), 0) FROM notes)"
+        )
+        .fetch_one(&self.db.sqlite)
+        .await?;
+        let mut seen_contents: HashSet<String> = HashSet::new();
+
+        // Each distinct content once, under the path it was first seen at
+        let contents: Vec<(String, String, String)> = sqlx::query_as(
+            "SELECT content_hash, file_path, id FROM snapshots s
+             WHERE rowid <= ?1
+               AND timestamp = (SELECT MIN(timestamp) FROM snapshots WHERE content_hash = s.content_hash AND rowid <= ?1)
+             GROUP BY content_hash"
+        )
+        .bind(cursor.0)
+        .fetch_all(&self.db.sqlite)
+        .await?;
+        for (content_hash, file_path, snapshot_id) in contents {
+            seen_contents.insert(content_hash.clone());
+            let meta = SnapshotMeta { snapshot_id, file_path, content_hash };
+            self.reindex_content(&engine, meta, &mut stats, &mut lexical).await?;
+        }
+
+        // A new file's patch is the whole file, already covered above
+        let changes: Vec<(String, String, String, String)> = sqlx::query_as(
+            "SELECT id, file_path, content_hash, diff_patch FROM snapshots WHERE rowid <= ? AND diff_patch NOT LIKE '--- /dev/null%'"
+        )
+        .bind(cursor.0)
+        .fetch_all(&self.db.sqlite)
+        .await?;
+        for (snapshot_id, file_path, content_hash, patch) in changes {
+            let meta = SnapshotMeta { snapshot_id, file_path, content_hash };
+            self.reindex_change(&engine, meta, &patch, &mut stats, &mut lexical).await?;
+        }
+
+        let notes: Vec<(String, String)> = sqlx::query_as("SELECT id, body FROM notes WHERE rowid <= ?")
+            .bind(cursor.1)
+            .fetch_all(&self.db.sqlite)
+            .await?;
+        for (note_id, body) in notes {
+            engine.write_note_vector(&note_id, &body).await?;
+            stats.notes += 1;
+        }
+
+        let mut conn = self.db.sqlite.acquire().await?;
+        while self.catch_up(&engine, &mut conn, &mut cursor, &mut seen_contents, &mut stats, &mut lexical).await? {}
+        drop(conn);
+
+        // The switch: the lexical index and the generation pointer change together. The first
+        // write takes SQLite's write lock, so the last catch-up can't miss anything.
+        let mut tx = self.db.sqlite.begin().await?;
+        sqlx::query("DELETE FROM chunks_fts").execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM indexed_content").execute(&mut *tx).await?;
+        self.catch_up(&engine, &mut tx, &mut cursor, &mut seen_contents, &mut stats, &mut lexical).await?;
+        search::write_lexical_in(&mut tx, &lexical).await?;
+        search::set_active_generation(&mut tx, &generation).await?;
+        tx.commit().await?;
+
+        config.save(&self.base_path)?;
+        drop_tables(&self.db.lancedb, old_index).await;
+        // The rebuilt generation covers everything tables from older index formats held
+        for table in search::legacy_tables(&self.db.lancedb).await? {
+            let _ = self.db.lancedb.drop_table(&table, &[]).await;
+        }
+
+        Ok(stats)
+    }
+
+    async fn reindex_content(&self, engine: &SearchEngine, meta: SnapshotMeta, stats: &mut ReindexStats, lexical: &mut Vec<SearchRecord>) -> Result<()> {
+        let Ok(content) = fs::read_to_string(self.objects_path.join(&meta.content_hash)) else {
+            stats.missing_objects += 1;
+            return Ok(());
+        };
+        let records = engine.embed_snapshot(&meta, Some(&content), None).await?;
+        stats.contents += 1;
+        stats.chunks += records.len() as u64;
+        engine.write_vectors(records.clone()).await?;
+        lexical.extend(records.into_iter().map(|r| SearchRecord { vector: Vec::new(), ..r }));
+        Ok(())
+    }
+
+    async fn reindex_change(&self, engine: &SearchEngine, meta: SnapshotMeta, patch: &str, stats: &mut ReindexStats, lexical: &mut Vec<SearchRecord>) -> Result<()> {
+        let records = engine.embed_snapshot(&meta, None, Some(patch)).await?;
+        stats.changes += 1;
+        stats.chunks += records.len() as u64;
+        engine.write_vectors(records.clone()).await?;
+        lexical.extend(records.into_iter().map(|r| SearchRecord { vector: Vec::new(), ..r }));
+        Ok(())
+    }
+
+    /// Indexes the snapshots and notes recorded after `cursor`, and moves it past them.
+    /// Returns whether there were any.
+    async fn catch_up(
+        &self,
+        engine: &SearchEngine,
+        conn: &mut sqlx::SqliteConnection,
+        cursor: &mut Cursor,
+        seen_contents: &mut HashSet<String>,
+        stats: &mut ReindexStats,
+        lexical: &mut Vec<SearchRecord>,
+    ) -> Result<bool> {
+        let snapshots: Vec<(i64, String, String, String, String)> = sqlx::query_as(
+            "SELECT rowid, id, file_path, content_hash, diff_patch FROM snapshots WHERE rowid > ? ORDER BY rowid"
+        )
+        .bind(cursor.0)
+        .fetch_all(&mut *conn)
+        .await?;
+        let notes: Vec<(i64, String, String)> = sqlx::query_as("SELECT rowid, id, body FROM notes WHERE rowid > ? ORDER BY rowid")
+            .bind(cursor.1)
+            .fetch_all(&mut *conn)
+            .await?;
+        let found = !snapshots.is_empty() || !notes.is_empty();
+
+        for (rowid, snapshot_id, file_path, content_hash, patch) in snapshots {
+            cursor.0 = rowid;
+            if seen_contents.insert(content_hash.clone()) {
+                let meta = SnapshotMeta { snapshot_id: snapshot_id.clone(), file_path: file_path.clone(), content_hash: content_hash.clone() };
+                self.reindex_content(engine, meta, stats, lexical).await?;
+            }
+            if !patch.starts_with("--- /dev/null") {
+                let meta = SnapshotMeta { snapshot_id, file_path, content_hash };
+                self.reindex_change(engine, meta, &patch, stats, lexical).await?;
+            }
+        }
+        for (rowid, note_id, body) in notes {
+            cursor.1 = rowid;
+            engine.write_note_vector(&note_id, &body).await?;
+            stats.notes += 1;
+        }
+        Ok(found)
+    }
+}
+
+/// The last snapshot and note rowids a reindex has covered.
+type Cursor = (i64, i64);
+
+/// Whether a `stasher reindex` is running for the project at `base_path`.
+pub fn reindex_running(base_path: &Path) -> bool {
+    base_path.join(LOCK_FILE).exists()
+}
+
+/// Held for the whole rebuild; the daemon won't start while it exists.
+struct ReindexLock(PathBuf);
+
+impl ReindexLock {
+    fn acquire(base_path: &Path) -> Result<Self> {
+        let path = base_path.join(LOCK_FILE);
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => Ok(Self(path)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => anyhow::bail!(
+                "Another reindex is running. If you are sure it's not, delete {}",
+                path.display()
+            ),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Drop for ReindexLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn next_generation(current: &str) -> String {
+    let n: u32 = current.strip_prefix('v').and_then(|n| n.parse().ok()).unwrap_or(1);
+    format!("v{}", n + 1)
+}
+
+/// Best effort: a table that's already gone is what we wanted anyway.
+async fn drop_tables(lancedb: &lancedb::Connection, index: &IndexInfo) {
+    for table in [index.chunks_table(), index.notes_table()] {
+        let _ = lancedb.drop_table(&table, &[]).await;
+    }
+}
@@ -0,0 +1,293 @@
+//! Undoing one snapshot's change while keeping everything saved since.
+//!
+//! The snapshot's stored patch is reverse-applied to the version it produced, giving that file
+//! as if the change had never been made. That is then merged three ways with the working file,
+//! using the snapshot's version as the common base, so later edits survive and only lines
+//! edited by both come out as conflicts.
+
+use super::HistoryManager;
+use anyhow::{Context, Result};
+use similar::{capture_diff_slices, Algorithm, DiffTag};
+
+pub struct RevertOutcome {
+    pub file_path: String,
+    /// Hunks in the snapshot's patch.
+    pub hunks: usize,
+    /// Regions left between conflict markers.
+    pub conflicts: usize,
+    /// The working file already didn't contain the change.
+    pub unchanged: bool,
+}
+
+/// One `@@` section of a stored patch, with its lines' newlines intact.
+struct PatchHunk {
+    new_start: usize,
+    old_lines: Vec<String>,
+    new_lines: Vec<String>,
+}
+
+impl HistoryManager {
+    /// Takes the change made by `reference` (or just its `hunk`, counted from 1) out of the
+    /// working file. The file is snapshotted first and the write journaled, so `stasher undo`
+    /// reverses it.
+    pub async fn revert(&self, reference: &str, hunk: Option<usize>) -> Result<RevertOutcome> {
+        let id = self.resolve_snapshot(reference, None).await?;
+        let (file_path, content_hash, patch): (String, String, String) =
+            sqlx::query_as("SELECT file_path, content_hash, diff_patch FROM snapshots WHERE id = ?")
+                .bind(&id)
+                .fetch_one(&self.db.sqlite)
+                .await?;
+        let after = std::fs::read_to_string(self.objects_path.join(&content_hash))
+            .context(format!("The version of {} saved by this snapshot is no longer stored", file_path))?;
+
+        let hunks = parse_patch(&patch);
+        let count = if patch.starts_with("--- /dev/null") { 1 } else { hunks.len() };
+        if count == 0 {
+            anyhow::bail!("Snapshot {} recorded no changes", reference);
+        }
+        if let Some(n) = hunk
+            && (n == 0 || n > count) {
+            anyhow::bail!("Snapshot {} has {} hunks; pick one from 1 to {}", reference, count, count);
+        }
+
+        let before = if patch.starts_with("--- /dev/null") {
+            // The snapshot created the file; reverting it empties the file
+            String::new()
+        } else {
+            let selected: Vec<&PatchHunk> = hunks.iter()
+                .enumerate()
+                .filter(|(i, _)| hunk.is_none_or(|n| n == i + 1))
+                .map(|(_, h)| h)
+                .collect();
+            reverse_apply(&after, &selected)
+                .context(format!("Snapshot {}'s patch doesn't match its stored version", reference))?
+        };
+
+        let target = self.base_path.join(&file_path);
+        if !target.exists() {
+            anyhow::bail!("{} is no longer on disk; use `stasher restore` to bring it back", file_path);
+        }
+        let working = std::fs::read_to_string(&target)
+            .context(format!("Failed to read {}", file_path))?;
+
+        let label = format!("revert of {}", &id[..8]);
+        let (merged, conflicts) = merge3(&after, &working, &before, &label);
+        let unchanged = merged == working;
+        if !unchanged {
+            // Snapshots the file first and journals the write, so `stasher undo` can reverse it
+            self.apply_edit("revert", reference, &file_path, Some(&working), &merged).await?;
+        }
+        Ok(RevertOutcome { file_path, hunks: count, conflicts, unchanged })
+    }
+}
+
+fn parse_patch(patch: &str) -> Vec<PatchHunk> {
+    let mut hunks: Vec<PatchHunk> = Vec::new();
+    // Which side the previous line went to, for "\ No newline at end of file"
+    let mut last: Option<(bool, bool)> = None;
+    for line in patch.split_inclusive('\n') {
+        if let Some(header) = line.strip_prefix("@@ -") {
+            let new_start = header.split_once(" +")
+                .and_then(|(_, rest)| rest.split([',', ' ']).next())
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(0);
+            hunks.push(PatchHunk { new_start, old_lines: Vec::new(), new_lines: Vec::new() });
+            last = None;
+            continue;
+        }
+        let Some(current) = hunks.last_mut() else { continue };
+        if line.starts_with('\\') {
+            if let Some((old, new)) = last {
+                if old && let Some(l) = current.old_lines.last_mut() {
+                    l.pop();
+                }
+                if new && let Some(l) = current.new_lines.last_mut() {
+                    l.pop();
+                }
+            }
+            continue;
+        }
+        let text = line.get(1..).unwrap_or_default().to_string();
+        last = match line.chars().next() {
+            Some(' ') => {
+                current.old_lines.push(text.clone());
+                current.new_lines.push(text);
+                Some((true, true))
+            }
+            Some('-') => {
+                current.old_lines.push(text);
+                Some((true, false))
+            }
+            Some('+') => {
+                current.new_lines.push(text);
+                Some((false, true))
+            }
+            _ => None,
+        };
+    }
+    hunks
+}
+
+/// Puts each hunk's old lines back in place of its new ones.
+fn reverse_apply(after: &str, hunks: &[&PatchHunk]) -> Result<String> {
+    let mut lines: Vec<String> = after.split_inclusive('\n').map(String::from).collect();
+    // From the bottom up, so earlier hunks' line numbers still hold
+    for hunk in hunks.iter().rev() {
+        // An empty new side is numbered by the line before it
+        let start = if hunk.new_lines.is_empty() { hunk.new_start } else { hunk.new_start.saturating_sub(1) };
+        let end = start + hunk.new_lines.len();
+        if end > lines.len() || lines[start..end] != hunk.new_lines[..] {
+            anyhow::bail!("hunk at line {} no longer applies", hunk.new_start);
+        }
+        lines.splice(start..end, hunk.old_lines.iter().cloned());
+    }
+    Ok(lines.concat())
+}
+
+/// A run of base lines one side replaced.
+struct Edit<'a> {
+    start: usize,
+    end: usize,
+    lines: &'a [&'a str],
+}
+
+fn edits<'a>(base: &[&str], side: &'a [&'a str]) -> Vec<Edit<'a>> {
+    capture_diff_slices(Algorithm::Myers, base, side)
+        .into_iter()
+        .filter(|op| op.tag() != DiffTag::Equal)
+        .map(|op| Edit { start: op.old_range().start, end: op.old_range().end, lines: &side[op.new_range()] })
+        .collect()
+}
+
+/// Merges the changes `ours` and `theirs` each made to `base`. Where both changed the same
+/// lines differently, both versions are kept between conflict markers. Returns the text and
+/// the number of conflicts.
+fn merge3(base: &str, ours: &str, theirs: &str, theirs_label: &str) -> (String, usize) {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs: Vec<&str> = theirs.split_inclusive('\n').collect();
+    let ours_edits = edits(&base, &ours);
+    let theirs_edits = edits(&base, &theirs);
+
+    let mut out = String::new();
+    let mut conflicts = 0;
+    let mut pos = 0;
+    let (mut i, mut j) = (0, 0);
+    while i < ours_edits.len() || j < theirs_edits.len() {
+        // Gather every edit from either side that overlaps the first one
+        let first_ours = j >= theirs_edits.len()
+            || (i < ours_edits.len() && ours_edits[i].start <= theirs_edits[j].start);
+        let (start, mut end) = if first_ours {
+            (ours_edits[i].start, ours_edits[i].end)
+        } else {
+            (theirs_edits[j].start, theirs_edits[j].end)
+        };
+        let (ours_from, theirs_from) = (i, j);
+        loop {
+            let overlaps = |e: &Edit| e.start < end || e.start == start || (e.start == end && (e.start == e.end || start == end));
+            if i < ours_edits.len() && overlaps(&ours_edits[i]) {
+                end = end.max(ours_edits[i].end);
+                i += 1;
+            } else if j < theirs_edits.len() && overlaps(&theirs_edits[j]) {
+                end = end.max(theirs_edits[j].end);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+
+        out.push_str(&base[pos..start].concat());
+        let ours_version = apply(&base, start, end, &ours_edits[ours_from..i]);
+        let theirs_version = apply(&base, start, end, &theirs_edits[theirs_from..j]);
+        if ours_from == i {
+            out.push_str(&theirs_version);
+        } else if theirs_from == j || ours_version == theirs_version {
+            out.push_str(&ours_version);
+        } else {
+            conflicts += 1;
+            out.push_str("<<<<<<< working copy\n");
+            push_terminated(&mut out, &ours_version);
+            out.push_str("=======\n");
+            push_terminated(&mut out, &theirs_version);
+            out.push_str(&format!(">>>>>>> {}\n", theirs_label));
+        }
+        pos = end;
+    }
+    out.push_str(&base[pos..].concat());
+    (out, conflicts)
+}
+
+/// `base[start..end]` with `edits` (all inside that range) applied.
+fn apply(base: &[&str], start: usize, end: usize, edits: &[Edit]) -> String {
+    let mut out = String::new();
+    let mut pos = start;
+    for edit in edits {
+        out.push_str(&base[pos..edit.start].concat());
+        out.push_str(&edit.lines.concat());
+        pos = edit.end;
+    }
+    out.push_str(&base[pos..end].concat());
+    out
+}
+
+fn push_terminated(out: &mut String, text: &str) {
+    out.push_str(text);
+    if !text.is_empty() && !text.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge3, parse_patch, reverse_apply};
+
+    #[test]
+    fn parses_hunks_with_both_sides() {
+        let hunks = parse_patch("--- a.txt\n+++ a.txt\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n@@ -10,2 +12,2 @@\n x\n-y\n+Y\n");
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(hunks[0].new_start, 1);
+        assert_eq!(hunks[0].old_lines, ["a\n", "b\n", "c\n"]);
+        assert_eq!(hunks[0].new_lines, ["a\n", "B\n", "c\n"]);
+        assert_eq!(hunks[1].new_start, 12);
+    }
+
+    #[test]
+    fn parses_missing_final_newlines() {
+        let hunks = parse_patch("@@ -1 +1 @@\n-old\n\\ No newline at end of file\n+new\n\\ No newline at end of file\n");
+        assert_eq!(hunks[0].old_lines, ["old"]);
+        assert_eq!(hunks[0].new_lines, ["new"]);
+    }
+
+    #[test]
+    fn reverse_applies_hunks_bottom_up() {
+        let hunks = parse_patch("@@ -1,2 +1,3 @@\n a\n+x\n b\n@@ -5,2 +6,1 @@\n e\n-f\n");
+        let hunks: Vec<_> = hunks.iter().collect();
+        assert_eq!(reverse_apply("a\nx\nb\nc\nd\ne\n", &hunks).unwrap(), "a\nb\nc\nd\ne\nf\n");
+    }
+
+    #[test]
+    fn reverse_apply_refuses_changed_lines() {
+        let hunks = parse_patch("@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+        let hunks: Vec<_> = hunks.iter().collect();
+        assert!(reverse_apply("a\nZ\nc\n", &hunks).is_err());
+    }
+
+    #[test]
+    fn merges_separate_changes() {
+        let (merged, conflicts) = merge3("1\n2\n3\n4\n5\n", "1\nO\n3\n4\n5\n", "1\n2\n3\n4\nT\n", "theirs");
+        assert_eq!(merged, "1\nO\n3\n4\nT\n");
+        assert_eq!(conflicts, 0);
+    }
+
+    #[test]
+    fn merges_identical_changes_once() {
+        assert_eq!(merge3("1\n2\n3\n", "1\nX\n3\n", "1\nX\n3\n", "theirs"), ("1\nX\n3\n".to_string(), 0));
+    }
+
+    #[test]
+    fn marks_conflicting_changes() {
+        let (merged, conflicts) = merge3("1\n2\n3\n", "1\nO\n3\n", "1\nT\n3\n", "snapshot abc1234");
+        assert_eq!(merged, "1\n<<<<<<< working copy\nO\n=======\nT\n>>>>>>> snapshot abc1234\n3\n");
+        assert_eq!(conflicts, 1);
+    }
+}
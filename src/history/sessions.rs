@@ -0,0 +1,174 @@
+use super::{HistoryManager, SnapshotSummary};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use uuid::Uuid;
+
+/// Saves further apart than this are recorded in separate sessions.
+pub const SESSION_IDLE_GAP_MS: i64 = 30 * 60 * 1000;
+
+pub(super) struct SessionState {
+    trigger: String,
+    label: Option<String>,
+    active: Option<ActiveSession>,
+}
+
+struct ActiveSession {
+    id: String,
+    last_activity: i64,
+}
+
+impl Default for SessionState {
+    fn default() -> Self {
+        Self {
+            trigger: "manual".to_string(),
+            label: None,
+            active: None,
+        }
+    }
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct SessionSummary {
+    pub id: String,
+    pub start_time: i64,
+    pub end_time: Option<i64>,
+    pub label: Option<String>,
+    pub branch: Option<String>,
+    pub trigger_kind: Option<String>,
+    pub snapshot_count: i64,
+}
+
+const SESSION_SUMMARY_SELECT: &str =
+    "SELECT s.id, s.start_time, s.end_time, s.label, s.branch, s.trigger_kind,
+            (SELECT COUNT(*) FROM snapshots WHERE session_id = s.id) AS snapshot_count
+     FROM sessions s";
+
+impl HistoryManager {
+    /// Sets what started the upcoming work. The session row itself is only written once the
+    /// first snapshot lands, so read-only commands never leave empty sessions behind.
+    pub async fn start_session(&self, trigger: &str, label: Option<String>) -> Result<()> {
+        self.end_session().await?;
+
+        let mut state = self.session.lock().await;
+        state.trigger = trigger.to_string();
+        state.label = label;
+        Ok(())
+    }
+
+    /// Closes the active session, stamping its end time with the last recorded save.
+    pub async fn end_session(&self) -> Result<()> {
+        let mut state = self.session.lock().await;
+        if let Some(active) = state.active.take() {
+            self.close_session(&active).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the session a save at `now` belongs to, opening a new one when there is none
+    /// yet or the previous save was longer ago than [`SESSION_IDLE_GAP_MS`].
+    pub(super) async fn session_for_write(&self, now: i64) -> Result<String> {
+        let mut state = self.session.lock().await;
+
+        if let Some(active) = state.active.as_mut()
+            && now - active.last_activity <= SESSION_IDLE_GAP_MS {
+            active.last_activity = now;
+            return Ok(active.id.clone());
+        }
+        if let Some(stale) = state.active.take() {
+            self.close_session(&stale).await?;
+        }
+
+        let id = Uuid::new_v4().to_string();
+        sqlx::query("INSERT INTO sessions (id, start_time, label, branch, trigger_kind) VALUES (?, ?, ?, ?, ?)")
+            .bind(&id)
+            .bind(now)
+            .bind(&state.label)
+            .bind(current_git_branch(&self.base_path))
+            .bind(&state.trigger)
+            .execute(&self.db.sqlite)
+            .await?;
+
+        state.active = Some(ActiveSession { id: id.clone(), last_activity: now });
+        Ok(id)
+    }
+
+    async fn close_session(&self, session: &ActiveSession) -> Result<()> {
+        sqlx::query("UPDATE sessions SET end_time = ? WHERE id = ?")
+            .bind(session.last_activity)
+            .bind(&session.id)
+            .execute(&self.db.sqlite)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn list_sessions(&self, limit: u32) -> Result<Vec<SessionSummary>> {
+        let sessions = sqlx::query_as::<_, SessionSummary>(
+            &format!("{} ORDER BY s.start_time DESC LIMIT ?", SESSION_SUMMARY_SELECT)
+        )
+        .bind(limit)
+        .fetch_all(&self.db.sqlite)
+        .await?;
+
+        Ok(sessions)
+    }
+
+    /// Looks a session up by its full id or a unique prefix.
+    pub async fn get_session(&self, session_id: &str) -> Result<SessionSummary> {
+        let mut matches = sqlx::query_as::<_, SessionSummary>(
+            &format!("{} WHERE s.id = ? OR s.id LIKE ? LIMIT 2", SESSION_SUMMARY_SELECT)
+        )
+        .bind(session_id)
+        .bind(format!("{}%", session_id))
+        .fetch_all(&self.db.sqlite)
+        .await?;
+
+        match matches.len() {
+            0 => anyhow::bail!("Session {} not found", session_id),
+            1 => Ok(matches.remove(0)),
+            _ => anyhow::bail!("Session prefix {} is ambiguous", session_id),
+        }
+    }
+
+    pub async fn session_snapshots(&self, session_id: &str) -> Result<Vec<SnapshotSummary>> {
+        let snapshots = sqlx::query_as::<_, SnapshotSummary>(
+            "SELECT id, timestamp, lines_added, lines_removed, file_path, content_hash FROM snapshots
+             WHERE session_id = ? ORDER BY timestamp ASC"
+        )
+        .bind(session_id)
+        .fetch_all(&self.db.sqlite)
+        .await?;
+
+        Ok(snapshots)
+    }
+
+    pub async fn label_session(&self, session_id: &str, label: &str) -> Result<String> {
+        let session = self.get_session(session_id).await?;
+        sqlx::query("UPDATE sessions SET label = ? WHERE id = ?")
+            .bind(label)
+            .bind(&session.id)
+            .execute(&self.db.sqlite)
+            .await
+            .context("Failed to label session")?;
+        Ok(session.id)
+    }
+}
+
+/// Reads the checked-out branch straight from `.git/HEAD`, falling back to the short commit
+/// id when HEAD is detached.
+fn current_git_branch(base_path: &Path) -> Option<String> {
+    let mut git_dir = base_path.join(".git");
+    if git_dir.is_file() {
+        // Worktrees and submodules point at their real git dir
+        let pointer = fs::read_to_string(&git_dir).ok()?;
+        git_dir = base_path.join(pointer.strip_prefix("gitdir:")?.trim());
+    }
+
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: refs/heads/") {
+        Some(branch) => Some(branch.to_string()),
+        None => head.get(..7).map(|sha| sha.to_string()),
+    }
+}
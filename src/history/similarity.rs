@@ -0,0 +1,76 @@
+use super::HistoryManager;
+use anyhow::{Context, Result};
+use std::fs;
+
+/// Code to find lookalikes of, taken from the working tree or a snapshot.
+pub struct SimilarSource {
+    /// Where the code came from, e.g. `src/auth.rs:10-42 @ 1a2b3c4`.
+    pub label: String,
+    pub text: String,
+}
+
+impl HistoryManager {
+    /// Reads `target` (`path` or `path:start-end`) from disk, or from `snapshot` when given.
+    /// A snapshot on its own needs no path.
+    pub async fn similar_source(&self, target: Option<&str>, snapshot: Option<&str>) -> Result<SimilarSource> {
+        let (file, range) = match target {
+            Some(target) => {
+                let (file, range) = split_range(target)?;
+                (Some(file), range)
+            }
+            None => (None, None),
+        };
+
+        let (location, at, content) = match (snapshot, file) {
+            (Some(reference), file) => {
+                let id = self.resolve_snapshot(reference, file).await?;
+                let (hash, file_path): (String, String) = sqlx::query_as("SELECT content_hash, file_path FROM snapshots WHERE id = ?")
+                    .bind(&id)
+                    .fetch_one(&self.db.sqlite)
+                    .await?;
+                let content = fs::read_to_string(self.objects_path.join(&hash))
+                    .context("Failed to read historical object from CAS")?;
+                (file_path, format!(" @ {}", &id[..7]), content)
+            }
+            (None, Some(file)) => {
+                let path = self.base_path.join(self.to_stasher_relative(file));
+                let content = fs::read_to_string(&path).context(format!("Failed to read {}", file))?;
+                (file.to_string(), String::new(), content)
+            }
+            (None, None) => anyhow::bail!("Give a file, a line range or --snapshot to compare against"),
+        };
+
+        let (label, text) = match range {
+            Some((start, end)) => {
+                let lines: Vec<&str> = content.lines().collect();
+                if start > lines.len() {
+                    anyhow::bail!("Line {} is past the end of {} ({} lines)", start, location, lines.len());
+                }
+                let end = end.min(lines.len());
+                (format!("{}:{}-{}{}", location, start, end, at), lines[start - 1..end].join("\n"))
+            }
+            None => (format!("{}{}", location, at), content),
+        };
+        if text.trim().is_empty() {
+            anyhow::bail!("{} is empty", label);
+        }
+        Ok(SimilarSource { label, text })
+    }
+}
+
+/// Splits `path:start-end` into the path and a 1-based inclusive line range.
+fn split_range(target: &str) -> Result<(&str, Option<(usize, usize)>)> {
+    let Some((file, range)) = target.rsplit_once(':') else {
+        return Ok((target, None));
+    };
+    let Some((start, end)) = range.split_once('-') else {
+        return Ok((target, None));
+    };
+    let (Ok(start), Ok(end)) = (start.parse::<usize>(), end.parse::<usize>()) else {
+        return Ok((target, None));
+    };
+    if start == 0 || end < start {
+        anyhow::bail!("Invalid line range {}", range);
+    }
+    Ok((file, Some((start, end))))
+}
@@ -0,0 +1,245 @@
+use super::{parse_time_spec, HistoryManager};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::HashMap;
+
+pub enum TagTarget {
+    /// A single snapshot, given as an id, id prefix or another tag.
+    Snapshot(String),
+    /// The latest snapshot of every tracked file at the time of tagging.
+    AllFiles,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct TagSummary {
+    pub name: String,
+    pub timestamp: i64,
+    pub all_files: bool,
+    pub created_at: i64,
+    pub snapshot_count: i64,
+}
+
+/// Shorter id prefixes are refused: they match too much, and would shadow times like `1d`.
+const MIN_ID_PREFIX: usize = 4;
+
+/// What a reference names when it isn't a tag.
+pub(super) enum Reference {
+    Snapshot(String),
+    Time(i64),
+}
+
+#[derive(sqlx::FromRow)]
+struct TagRow {
+    timestamp: i64,
+    all_files: bool,
+}
+
+impl HistoryManager {
+    pub async fn create_tag(&self, name: &str, target: TagTarget) -> Result<TagSummary> {
+        if name.is_empty() || name.chars().any(char::is_whitespace) {
+            anyhow::bail!("Tag names must be non-empty and contain no whitespace");
+        }
+        if self.find_tag(name).await?.is_some() {
+            anyhow::bail!("Tag {} already exists", name);
+        }
+
+        let now = Utc::now().timestamp_millis();
+        let (timestamp, all_files, snapshot_ids) = match target {
+            TagTarget::Snapshot(reference) => {
+                let id = self.resolve_snapshot(&reference, None).await?;
+                let timestamp: i64 = sqlx::query_scalar("SELECT timestamp FROM snapshots WHERE id = ?")
+                    .bind(&id)
+                    .fetch_one(&self.db.sqlite)
+                    .await?;
+                (timestamp, false, vec![id])
+            }
+            TagTarget::AllFiles => {
+                let ids = self.tree_at(now).await?.into_iter().map(|e| e.snapshot_id).collect();
+                (now, true, ids)
+            }
+        };
+
+        let mut tx = self.db.sqlite.begin().await?;
+        sqlx::query("INSERT INTO tags (name, timestamp, all_files, created_at) VALUES (?, ?, ?, ?)")
+            .bind(name)
+            .bind(timestamp)
+            .bind(all_files)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        for id in &snapshot_ids {
+            sqlx::query("INSERT INTO tag_snapshots (tag_name, snapshot_id) VALUES (?, ?)")
+                .bind(name)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(TagSummary {
+            name: name.to_string(),
+            timestamp,
+            all_files,
+            created_at: now,
+            snapshot_count: snapshot_ids.len() as i64,
+        })
+    }
+
+    pub async fn list_tags(&self) -> Result<Vec<TagSummary>> {
+        let tags = sqlx::query_as::<_, TagSummary>(
+            "SELECT t.name, t.timestamp, t.all_files, t.created_at,
+                    (SELECT COUNT(*) FROM tag_snapshots WHERE tag_name = t.name) AS snapshot_count
+             FROM tags t ORDER BY t.timestamp DESC"
+        )
+        .fetch_all(&self.db.sqlite)
+        .await?;
+
+        Ok(tags)
+    }
+
+    pub async fn delete_tag(&self, name: &str) -> Result<()> {
+        let mut tx = self.db.sqlite.begin().await?;
+        sqlx::query("DELETE FROM tag_snapshots WHERE tag_name = ?")
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+        let deleted = sqlx::query("DELETE FROM tags WHERE name = ?")
+            .bind(name)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        tx.commit().await?;
+
+        if deleted == 0 {
+            anyhow::bail!("Tag {} not found", name);
+        }
+        Ok(())
+    }
+
+    /// Tag names attached to each of the given snapshots.
+    pub async fn snapshot_tags(&self, snapshot_ids: &[String]) -> Result<HashMap<String, Vec<String>>> {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            "SELECT snapshot_id, tag_name FROM tag_snapshots
+             WHERE snapshot_id IN (SELECT value FROM json_each(?))
+             ORDER BY tag_name"
+        )
+        .bind(serde_json::to_string(snapshot_ids)?)
+        .fetch_all(&self.db.sqlite)
+        .await?;
+
+        let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+        for (snapshot_id, tag_name) in rows {
+            tags.entry(snapshot_id).or_default().push(tag_name);
+        }
+        Ok(tags)
+    }
+
+    /// Turns whatever the user typed where a snapshot is expected into a snapshot id.
+    ///
+    /// Accepts a tag name, a point in time (see [`parse_time_spec`]) or a snapshot id or unique
+    /// prefix, in that order of precedence. Tags covering all files and points in time need
+    /// `file_path` to pick the right snapshot.
+    pub async fn resolve_snapshot(&self, reference: &str, file_path: Option<&str>) -> Result<String> {
+        let rel_path = file_path.map(|f| self.to_stasher_relative(f));
+
+        if let Some(tag) = self.find_tag(reference).await? {
+            if !tag.all_files {
+                let id: String = sqlx::query_scalar("SELECT snapshot_id FROM tag_snapshots WHERE tag_name = ?")
+                    .bind(reference)
+                    .fetch_one(&self.db.sqlite)
+                    .await?;
+                return Ok(id);
+            }
+
+            let (rel_path, file_path) = rel_path.zip(file_path)
+                .context(format!("Tag {} covers all files; specify which file you mean", reference))?;
+            let id: Option<String> = sqlx::query_scalar(
+                "SELECT s.id FROM tag_snapshots t JOIN snapshots s ON s.id = t.snapshot_id
+                 WHERE t.tag_name = ? AND (s.file_path = ? OR s.file_path LIKE ?)"
+            )
+            .bind(reference)
+            .bind(&rel_path)
+            .bind(format!("%/{}", file_path))
+            .fetch_optional(&self.db.sqlite)
+            .await?;
+            return id.context(format!("Tag {} has no version of {}", reference, file_path));
+        }
+
+        let timestamp = match self.classify_reference(reference).await? {
+            Reference::Snapshot(id) => return Ok(id),
+            Reference::Time(timestamp) => timestamp,
+        };
+        let (rel_path, file_path) = rel_path.zip(file_path)
+            .context(format!("{} is a point in time; specify which file you mean", reference))?;
+        let id: Option<String> = sqlx::query_scalar(
+            "SELECT id FROM snapshots WHERE (file_path = ? OR file_path LIKE ?) AND timestamp <= ?
+             ORDER BY timestamp DESC LIMIT 1"
+        )
+        .bind(&rel_path)
+        .bind(format!("%/{}", file_path))
+        .bind(timestamp)
+        .fetch_optional(&self.db.sqlite)
+        .await?;
+
+        id.context(format!("No version of {} exists at {}", file_path, reference))
+    }
+
+    /// Reads a reference that isn't a tag as a point in time, or else as a snapshot id or
+    /// unique prefix. Bare numbers short enough to be an id prefix are tried as one first,
+    /// before falling back to epoch milliseconds.
+    pub(super) async fn classify_reference(&self, reference: &str) -> Result<Reference> {
+        let time = parse_time_spec(reference).ok();
+        let numeric = reference.chars().all(|c| c.is_ascii_digit());
+        if let Some(timestamp) = time
+            && !numeric {
+            return Ok(Reference::Time(timestamp));
+        }
+
+        if reference.len() >= MIN_ID_PREFIX {
+            let mut ids: Vec<String> = sqlx::query_scalar("SELECT id FROM snapshots WHERE substr(id, 1, ?) = ? LIMIT 2")
+                .bind(reference.len() as i64)
+                .bind(reference)
+                .fetch_all(&self.db.sqlite)
+                .await?;
+            match ids.len() {
+                1 => return Ok(Reference::Snapshot(ids.remove(0))),
+                2 => anyhow::bail!("Snapshot prefix {} is ambiguous; give more characters", reference),
+                _ => {}
+            }
+        }
+
+        match time {
+            Some(timestamp) => Ok(Reference::Time(timestamp)),
+            None if reference.len() < MIN_ID_PREFIX => anyhow::bail!(
+                "{} is not a tag or time, and snapshot ids need at least {} characters",
+                reference,
+                MIN_ID_PREFIX
+            ),
+            None => anyhow::bail!("{} is not a tag, snapshot id or time", reference),
+        }
+    }
+
+    async fn find_tag(&self, name: &str) -> Result<Option<TagRow>> {
+        let tag = sqlx::query_as::<_, TagRow>("SELECT timestamp, all_files FROM tags WHERE name = ?")
+            .bind(name)
+            .fetch_optional(&self.db.sqlite)
+            .await?;
+        Ok(tag)
+    }
+
+    /// The moment a tag or time reference stands for, plus the tag's snapshots when it has any.
+    pub(super) async fn resolve_moment(&self, reference: &str) -> Result<(i64, Option<Vec<String>>)> {
+        if let Some(tag) = self.find_tag(reference).await? {
+            let ids: Vec<String> = sqlx::query_scalar("SELECT snapshot_id FROM tag_snapshots WHERE tag_name = ?")
+                .bind(reference)
+                .fetch_all(&self.db.sqlite)
+                .await?;
+            return Ok((tag.timestamp, Some(ids)));
+        }
+
+        let timestamp = parse_time_spec(reference)
+            .context(format!("{} is not a tag or time", reference))?;
+        Ok((timestamp, None))
+    }
+}
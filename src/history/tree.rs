@@ -0,0 +1,75 @@
+use super::journal::RestorePlan;
+use super::HistoryManager;
+use anyhow::Result;
+use std::collections::HashSet;
+
+/// One file's version within a reconstructed project state.
+#[derive(sqlx::FromRow)]
+pub struct TreeEntry {
+    pub snapshot_id: String,
+    pub file_path: String,
+    pub content_hash: String,
+}
+
+impl HistoryManager {
+    /// The latest recorded version of every file as of `timestamp`, leaving out files that
+    /// were deleted or moved away after that version and before `timestamp`.
+    pub async fn tree_at(&self, timestamp: i64) -> Result<Vec<TreeEntry>> {
+        let mut entries = sqlx::query_as::<_, TreeEntry>(
+            "SELECT s.id AS snapshot_id, s.file_path, s.content_hash FROM snapshots s
+             WHERE s.timestamp = (SELECT MAX(timestamp) FROM snapshots WHERE file_path = s.file_path AND timestamp <= ?)
+               AND NOT EXISTS (SELECT 1 FROM file_deletions d
+                               WHERE d.file_path = s.file_path AND d.timestamp > s.timestamp AND d.timestamp <= ?)
+             ORDER BY s.file_path"
+        )
+        .bind(timestamp)
+        .bind(timestamp)
+        .fetch_all(&self.db.sqlite)
+        .await?;
+
+        entries.dedup_by(|a, b| a.file_path == b.file_path);
+        Ok(entries)
+    }
+
+    /// The project state a tag or point in time refers to. Tags return exactly the snapshots
+    /// they were created with.
+    pub async fn resolve_tree(&self, reference: &str) -> Result<Vec<TreeEntry>> {
+        let (timestamp, tagged) = self.resolve_moment(reference).await?;
+        let Some(ids) = tagged else {
+            return self.tree_at(timestamp).await;
+        };
+
+        let ids: HashSet<String> = ids.into_iter().collect();
+        let entries = sqlx::query_as::<_, TreeEntry>(
+            "SELECT id AS snapshot_id, file_path, content_hash FROM snapshots ORDER BY file_path"
+        )
+        .fetch_all(&self.db.sqlite)
+        .await?
+        .into_iter()
+        .filter(|e| ids.contains(&e.snapshot_id))
+        .collect();
+
+        Ok(entries)
+    }
+
+    /// Plans rewriting the working tree to the state `reference` points at, limited to
+    /// `paths` (files or directories) when any are given.
+    pub async fn plan_checkout(&self, reference: &str, paths: &[String]) -> Result<RestorePlan> {
+        let prefixes: Vec<String> = paths.iter().map(|p| self.to_stasher_relative(p)).collect();
+        let entries = self.resolve_tree(reference).await?;
+        if entries.is_empty() {
+            anyhow::bail!("No recorded files at {}", reference);
+        }
+
+        let mut plan = RestorePlan::new("checkout", reference);
+        for entry in entries {
+            let selected = prefixes.is_empty() || prefixes.iter().any(|p| {
+                entry.file_path == *p || entry.file_path.starts_with(&format!("{}/", p.trim_end_matches('/')))
+            });
+            if selected {
+                self.plan_file(&mut plan, &entry.file_path, &entry.content_hash)?;
+            }
+        }
+        Ok(plan)
+    }
+}
@@ -3,6 +3,23 @@ use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
 use std::path::{Path, PathBuf};
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use crate::db::migrations::{self, Migration};
+
+/// Schema history for `hub.db`. Append new entries; never edit released ones.
+const HUB_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial projects table",
+        statements: &[
+            "CREATE TABLE IF NOT EXISTS projects (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                path TEXT NOT NULL UNIQUE,
+                last_active INTEGER NOT NULL
+            )",
+        ],
+    },
+];
 
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct ProjectInfo {
@@ -33,17 +50,7 @@ impl StasherHub {
 
         let pool = SqlitePool::connect_with(options).await?;
 
-        // Create the projects table if it doesn't exist
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS projects (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                name TEXT NOT NULL,
-                path TEXT NOT NULL UNIQUE,
-                last_active INTEGER NOT NULL
-            )"
-        )
-        .execute(&pool)
-        .await?;
+        migrations::run(&pool, &db_path, HUB_MIGRATIONS).await?;
 
         Ok(Self { pool, hub_dir })
     }
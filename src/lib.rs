@@ -0,0 +1,12 @@
+//! Stasher's library: everything the `stasher` binary runs, exposed so integration tests can
+//! drive it directly.
+
+pub mod bundle;
+pub mod config;
+pub mod daemon;
+pub mod db;
+pub mod diff;
+pub mod history;
+pub mod hub;
+pub mod search;
+pub mod server;
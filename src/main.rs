@@ -1,9 +1,4 @@
-mod db;
-mod daemon;
-mod history;
-mod search;
-mod hub;
-mod server;
+use stasher::{bundle, config, daemon, db, diff, history, hub, search, server};
 
 use std::path::{Path, PathBuf};
 use anyhow::Result;
@@ -22,32 +17,289 @@ enum Commands {
     /// Initialize a new Stasher history in the current directory
     Init,
     /// Start the background daemon
-    Daemon,
-    /// Search history using natural language
-    Ask { query: String },
+    Daemon {
+        /// Label attached to the sessions this daemon records
+        #[arg(short, long)]
+        label: Option<String>,
+    },
+    /// Search history using natural language, keywords or identifiers
+    Ask {
+        query: String,
+        /// Only match chunks containing the query verbatim (e.g. an identifier)
+        #[arg(long, conflicts_with = "regex")]
+        exact: bool,
+        /// Treat the query as a regular expression
+        #[arg(long)]
+        regex: bool,
+        /// Only search files matching this glob (e.g. 'src/auth/**')
+        #[arg(long)]
+        path: Option<String>,
+        /// Only search snapshots taken at or after this time (e.g. 3d, 2024-05-01)
+        #[arg(long)]
+        since: Option<String>,
+        /// Only search snapshots taken at or before this time (e.g. yesterday)
+        #[arg(long)]
+        until: Option<String>,
+        /// Only search snapshots from this session (id or prefix)
+        #[arg(long)]
+        session: Option<String>,
+        /// Only search snapshots taken on this git branch
+        #[arg(long)]
+        branch: Option<String>,
+        /// Only search files in this language (e.g. rust, python, typescript)
+        #[arg(long)]
+        lang: Option<String>,
+        /// Favour recent code: halve a result's score for every this many days of age
+        #[arg(long, value_name = "DAYS")]
+        decay: Option<f64>,
+        /// Show where code first appeared instead of its latest version
+        #[arg(long)]
+        first: bool,
+    },
     /// Show history for a file
     Show { file: String },
     /// Restore a file to a previous version
     Restore { 
         file: String, 
+        /// Snapshot id, tag or point in time to restore (defaults to the latest version)
         #[arg(short, long)]
         snapshot: Option<String> 
     },
+    /// Undo the change one snapshot made, keeping every edit saved since
+    Revert {
+        /// Snapshot id or single-snapshot tag
+        snapshot: String,
+        /// Only revert this hunk of the snapshot's diff (counted from 1)
+        #[arg(long)]
+        hunk: Option<usize>,
+    },
+    /// Choose hunks from an old version of a file to bring back, one at a time
+    Pick {
+        /// Snapshot id or single-snapshot tag holding the version to pick from
+        snapshot: String,
+        /// Show each hunk in two columns
+        #[arg(short = 'y', long)]
+        side_by_side: bool,
+    },
     /// Clean up old snapshots and unused objects
     Prune {
         #[arg(short, long, default_value_t = 30)]
         days: u32,
     },
-    /// Show the differences recorded in a specific snapshot
-    Diff { snapshot: String },
+    /// Manage embedding models for offline use
+    Model {
+        #[command(subcommand)]
+        action: ModelAction,
+    },
+    /// Rebuild the search index from stored history, optionally switching embedding model
+    Reindex {
+        /// fastembed model code to switch to (e.g. BAAI/bge-small-en-v1.5)
+        #[arg(short, long)]
+        model: Option<String>,
+    },
+    /// Compare snapshots, tags or points in time with each other or with the files on disk
+    Diff {
+        /// Up to two snapshot ids, tags or times to compare, then files or directories to limit
+        /// the comparison to. One snapshot on its own shows what that snapshot changed
+        #[arg(value_name = "REF|PATH")]
+        args: Vec<String>,
+        /// Compare against the files on disk
+        #[arg(long)]
+        working: bool,
+        /// Point in time to compare (give twice, or once to compare with the files on disk)
+        #[arg(long = "at", value_name = "TIME")]
+        at: Vec<String>,
+        /// Show old and new text in two columns
+        #[arg(short = 'y', long)]
+        side_by_side: bool,
+        /// Ignore whitespace, indentation and re-wrapped lines
+        #[arg(short = 'w', long)]
+        ignore_whitespace: bool,
+        /// Files or directories, after `--` when they could be taken for a reference or no
+        /// longer exist
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
     /// Show project statistics and daemon status
     Status,
     /// List all projects tracked by Stasher on this machine
     Projects,
     /// Search across all tracked projects
-    GlobalAsk { query: String },
+    GlobalAsk {
+        query: String,
+        /// Only match chunks containing the query verbatim (e.g. an identifier)
+        #[arg(long, conflicts_with = "regex")]
+        exact: bool,
+        /// Treat the query as a regular expression
+        #[arg(long)]
+        regex: bool,
+        /// Favour recent code: halve a result's score for every this many days of age
+        #[arg(long, value_name = "DAYS")]
+        decay: Option<f64>,
+        /// Show where code first appeared instead of its latest version
+        #[arg(long)]
+        first: bool,
+        /// Give up on projects that haven't answered after this many seconds
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+    },
+    /// Find code similar to a file, a line range or a snapshot
+    Similar {
+        /// File to compare against, optionally with a line range (e.g. src/auth.rs:10-42)
+        #[arg(required_unless_present = "snapshot")]
+        target: Option<String>,
+        /// Use this snapshot's version instead of the file on disk
+        #[arg(short, long)]
+        snapshot: Option<String>,
+        /// Search every registered project, not just this one
+        #[arg(long)]
+        global: bool,
+        /// Only search files matching this glob (e.g. 'src/auth/**')
+        #[arg(long)]
+        path: Option<String>,
+        /// Favour recent code: halve a result's score for every this many days of age
+        #[arg(long, value_name = "DAYS")]
+        decay: Option<f64>,
+        /// Show where code first appeared instead of its latest version
+        #[arg(long)]
+        first: bool,
+    },
+    /// Search every stored version of every file with a regular expression
+    Grep {
+        pattern: String,
+        /// Only search files matching this glob (e.g. 'src/auth/**')
+        #[arg(long)]
+        path: Option<String>,
+        /// Only search versions saved at or after this time (e.g. 3d, 2024-05-01)
+        #[arg(long)]
+        since: Option<String>,
+        /// Only search versions saved at or before this time (e.g. yesterday)
+        #[arg(long)]
+        until: Option<String>,
+        /// Match case-insensitively
+        #[arg(short, long)]
+        ignore_case: bool,
+    },
+    /// Find the snapshots where a piece of text appeared or disappeared
+    Pickaxe {
+        text: String,
+        /// Only follow this file (and the files it was moved from)
+        file: Option<String>,
+    },
+    /// Show which save last changed each line of a file
+    Blame { file: String },
     /// Start the Stasher Hub UI Dashboard (local web server)
     Serve,
+    /// Export or import a portable history bundle
+    Bundle {
+        #[command(subcommand)]
+        action: BundleAction,
+    },
+    /// Tag a snapshot or the current state of every file (lists tags when no name is given)
+    Tag {
+        name: Option<String>,
+        /// Tag a single snapshot instead of the whole project
+        #[arg(short, long, conflicts_with = "all_files")]
+        snapshot: Option<String>,
+        /// Tag the latest version of every file (the default)
+        #[arg(long)]
+        all_files: bool,
+        /// Delete the tag instead of creating it
+        #[arg(short, long, conflicts_with_all = ["snapshot", "all_files"])]
+        delete: bool,
+    },
+    /// Restore the project (or some paths) to a tag or point in time
+    Checkout {
+        /// Tag name or point in time (e.g. 2h, yesterday, 2024-05-01T10:00:00Z)
+        target: String,
+        /// Only restore these files or directories
+        paths: Vec<String>,
+    },
+    /// Reverse the last restore, checkout, revert or pick
+    Undo,
+    /// Attach, list or remove markdown notes on snapshots and sessions
+    Note {
+        #[command(subcommand)]
+        action: NoteAction,
+    },
+    /// List, inspect and label recording sessions
+    Sessions {
+        #[command(subcommand)]
+        action: SessionAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum NoteAction {
+    /// Add a note to a snapshot (id or tag) or session
+    Add {
+        target: String,
+        /// Note text (markdown). Use '-' to read it from stdin
+        text: String,
+        /// Treat the target as a session id
+        #[arg(long, conflicts_with = "snapshot")]
+        session: bool,
+        /// Treat the target as a snapshot id, tag or time
+        #[arg(long)]
+        snapshot: bool,
+    },
+    /// List the notes on a snapshot or session
+    List {
+        target: String,
+        /// Treat the target as a session id
+        #[arg(long)]
+        session: bool,
+    },
+    /// Delete a note
+    Remove { id: String },
+}
+
+#[derive(Subcommand)]
+enum SessionAction {
+    /// List recent sessions
+    List {
+        #[arg(short, long, default_value_t = 20)]
+        limit: u32,
+    },
+    /// Show a session and the snapshots it recorded
+    Show { id: String },
+    /// Attach a label to a session
+    Label { id: String, label: String },
+}
+
+#[derive(Subcommand)]
+enum BundleAction {
+    /// Pack sessions, snapshots and objects into a single archive file
+    Create {
+        file: PathBuf,
+        /// Only include files matching this glob (e.g. 'src/**')
+        #[arg(long)]
+        path: Option<String>,
+        /// Only include snapshots taken at or after this time (e.g. 3d, 2024-05-01)
+        #[arg(long)]
+        since: Option<String>,
+        /// Only include snapshots taken at or before this time
+        #[arg(long)]
+        until: Option<String>,
+        /// Also include the semantic search vectors
+        #[arg(long)]
+        vectors: bool,
+    },
+    /// Merge a bundle into this project's history
+    Import { file: PathBuf },
+}
+
+#[derive(Subcommand)]
+enum ModelAction {
+    /// Install a model from a directory or .tar/.tar.gz archive, without network access
+    Install {
+        /// Directory or archive laid out like the model's Hugging Face repository
+        #[arg(long)]
+        from: PathBuf,
+        /// Model code to install as (defaults to the configured model)
+        #[arg(short, long)]
+        model: Option<String>,
+    },
 }
 
 fn find_stasher_root(start_path: &Path) -> Option<PathBuf> {
@@ -63,6 +315,192 @@ fn find_stasher_root(start_path: &Path) -> Option<PathBuf> {
     None
 }
 
+fn format_ago(timestamp: i64) -> String {
+    let dt = chrono::Utc::now().timestamp_millis() - timestamp;
+    if dt < 60000 {
+        format!("{}s ago", dt / 1000)
+    } else if dt < 3600000 {
+        format!("{}m ago", dt / 60000)
+    } else {
+        format!("{}h ago", dt / 3600000)
+    }
+}
+
+fn print_session_line(session: &history::SessionSummary) {
+    use colored::Colorize;
+
+    let duration = match session.end_time {
+        Some(end) => format!("{}m", (end - session.start_time) / 60000),
+        None => "open".to_string(),
+    };
+    let mut details = vec![
+        format!("{} snapshots", session.snapshot_count),
+        duration,
+    ];
+    if let Some(trigger) = &session.trigger_kind {
+        details.push(trigger.clone());
+    }
+    if let Some(branch) = &session.branch {
+        details.push(format!("⎇ {}", branch));
+    }
+
+    println!(
+        "[{}] {} | {}{}",
+        &session.id[..7].bright_white().bold(),
+        format_ago(session.start_time).yellow(),
+        details.join(" · ").dimmed(),
+        session.label.as_ref().map(|l| format!(" \"{}\"", l).magenta().to_string()).unwrap_or_default()
+    );
+}
+
+fn search_mode(exact: bool, regex: bool) -> search::SearchMode {
+    if exact {
+        search::SearchMode::Exact
+    } else if regex {
+        search::SearchMode::Regex
+    } else {
+        search::SearchMode::Hybrid
+    }
+}
+
+fn search_ranking(decay: Option<f64>, first: bool) -> Result<search::Ranking> {
+    if let Some(days) = decay
+        && days <= 0.0 {
+        anyhow::bail!("--decay must be a positive number of days");
+    }
+    Ok(search::Ranking {
+        half_life_days: decay,
+        prefer: if first { search::Prefer::First } else { search::Prefer::Latest },
+    })
+}
+
+/// Says whether a search hit is a file as saved or lines a snapshot added or removed.
+fn describe_hit(hit: &search::SearchHit) -> String {
+    let short_id = &hit.shown().snapshot_id[..7];
+    match hit.record.kind.as_str() {
+        "added" => format!("added in snapshot {}", short_id),
+        "removed" => format!("removed in snapshot {}", short_id),
+        _ if hit.occurrences.len() > 1 => format!("in {} snapshots", hit.occurrences.len()),
+        _ => format!("snapshot {}", short_id),
+    }
+}
+
+/// Prints a unified diff with added lines green, removed lines red and hunk headers blue.
+fn print_patch(diff: &str) {
+    use colored::Colorize;
+    for line in diff.lines() {
+        if line.starts_with('+') && !line.starts_with("+++") {
+            println!("{}", line.green());
+        } else if line.starts_with('-') && !line.starts_with("---") {
+            println!("{}", line.red());
+        } else if line.starts_with("@@") {
+            println!("{}", line.blue());
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+/// Lists the projects a global search couldn't cover.
+fn print_search_failures(failures: &[(String, String)]) {
+    use colored::Colorize;
+    if failures.is_empty() {
+        return;
+    }
+    println!("\n{}", format!("⚠️ {} projects not searched:", failures.len()).yellow());
+    for (project, reason) in failures {
+        println!("   {} {}", project.bold(), reason.dimmed());
+    }
+}
+
+/// Shows when and where a hit's preferred appearance was recorded, and how it scored.
+fn print_ranking(hit: &search::SearchHit) {
+    use colored::Colorize;
+    let shown = hit.shown();
+    let when = chrono::DateTime::from_timestamp_millis(shown.timestamp)
+        .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default();
+    let mut line = format!("   {} · {} ({}) · score {:.4}", &shown.snapshot_id[..7], when, format_ago(shown.timestamp), hit.score);
+    if let Some(distance) = hit.distance {
+        line.push_str(&format!(" · distance {:.3}", distance));
+    }
+    if hit.folded > 0 {
+        line.push_str(&format!(" · +{} more from this file", hit.folded));
+    }
+    println!("{}", line.dimmed());
+}
+
+/// Lists the snapshots a deduplicated hit appeared in, newest first.
+fn print_occurrences(hit: &search::SearchHit, max: usize) {
+    use colored::Colorize;
+    if hit.occurrences.len() < 2 {
+        return;
+    }
+    for occurrence in hit.occurrences.iter().take(max) {
+        println!("{}", format!(
+            "   ↳ {}  {}  {}",
+            &occurrence.snapshot_id[..7],
+            occurrence.file_path,
+            format_ago(occurrence.timestamp)
+        ).dimmed());
+    }
+    if hit.occurrences.len() > max {
+        println!("{}", format!("   ↳ ... and {} more", hit.occurrences.len() - max).dimmed());
+    }
+}
+
+/// Prints the first `max_lines` of a search hit with their line numbers in the original file.
+fn print_snippet(res: &search::SearchRecord, max_lines: usize) {
+    use colored::Colorize;
+    let total = res.content.lines().count();
+    for (offset, line) in res.content.lines().take(max_lines).enumerate() {
+        let marker = match res.kind.as_str() {
+            "added" => "+".green(),
+            "removed" => "-".red(),
+            _ => " ".normal(),
+        };
+        println!("{}{} {}", format!("{:>5} |", res.start_line as usize + offset).dimmed(), marker, line);
+    }
+    if total > max_lines {
+        println!("{}", format!("      | ... {} more lines", total - max_lines).dimmed());
+    }
+}
+
+/// Splits `stasher diff` arguments into up to two references and the paths after them, the way
+/// `git diff` does: an argument naming something on disk is a path, and everything after the
+/// first path is a path too.
+async fn split_diff_args(history: &history::HistoryManager, args: &[String]) -> Result<(Vec<(String, history::DiffSide)>, Vec<String>)> {
+    let mut sides = Vec::new();
+    let mut paths = Vec::new();
+    for arg in args {
+        if !paths.is_empty() || sides.len() == 2 {
+            paths.push(arg.clone());
+            continue;
+        }
+        let on_disk = Path::new(arg).exists();
+        match history.resolve_side(arg).await {
+            Ok(_) if on_disk => anyhow::bail!("'{}' is both a file and a snapshot, tag or time. Put paths after `--`.", arg),
+            Ok(side) => sides.push((arg.clone(), side)),
+            Err(_) if on_disk => paths.push(arg.clone()),
+            Err(e) => {
+                return Err(e.context(format!(
+                    "'{}' is not a file on disk or a snapshot, tag or time (put paths of deleted files after `--`)",
+                    arg
+                )));
+            }
+        }
+    }
+    Ok((sides, paths))
+}
+
+fn print_note(note: &history::Note) {
+    use colored::Colorize;
+    for (i, line) in note.body.lines().enumerate() {
+        let prefix = if i == 0 { format!("📝 [{}] ", &note.id[..7]) } else { "   ".repeat(4) };
+        println!("      {}{}", prefix.dimmed(), line.italic());
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
@@ -79,10 +517,15 @@ async fn main() -> Result<()> {
         Commands::Init => {
             println!("🚀 Initializing Stasher repository in {}...", base_path.display());
             let db = db::Database::init(&base_path).await?;
+            if !config::Config::path(&base_path).exists() {
+                config::Config::default().save(&base_path)?;
+            }
             let history = history::HistoryManager::new(std::sync::Arc::new(db), base_path.to_path_buf()).await?;
             
             // Perform initial sync of all files
+            history.start_session("init", None).await?;
             history.sync_all().await?;
+            history.end_session().await?;
 
             // Check if .gitignore exists and add .stasher if missing
             let gitignore_path = base_path.join(".gitignore");
@@ -108,8 +551,8 @@ async fn main() -> Result<()> {
             
             Ok(())
         }
-        Commands::Daemon => {
-            let lock_path = base_path.join(".stasher/daemon.pid");
+        Commands::Daemon { label } => {
+            let lock_path = base_path.join(daemon::PID_FILE);
             if lock_path.exists() {
                 let pid = std::fs::read_to_string(&lock_path).unwrap_or_default();
                 eprintln!("❌ Stasher daemon is already running (PID: {}).", pid);
@@ -117,12 +560,18 @@ async fn main() -> Result<()> {
                 return Ok(());
             }
             std::fs::write(&lock_path, std::process::id().to_string())?;
+            // Checked after writing the pid file, so a reindex starting now sees the daemon
+            if history::reindex_running(&base_path) {
+                let _ = std::fs::remove_file(&lock_path);
+                eprintln!("❌ `stasher reindex` is rebuilding the search index. Start the daemon once it finishes.");
+                return Ok(());
+            }
 
             println!("🚀 Initializing Stasher database in .stasher/ ...");
             let db = db::Database::init(&base_path).await?;
             println!("💾 Database ready. Starting daemon...");
             
-            let daemon = daemon::StasherDaemon::new(db, base_path.to_path_buf()).await?;
+            let daemon = daemon::StasherDaemon::new(db, base_path.to_path_buf(), label.clone()).await?;
             
             // Update last_active in global hub
             if let Ok(hub) = hub::StasherHub::init().await {
@@ -133,22 +582,49 @@ async fn main() -> Result<()> {
             let _ = std::fs::remove_file(lock_path);
             res
         }
-        Commands::Ask { query } => {
+        Commands::Ask { query, exact, regex, path, since, until, session, branch, lang, decay, first } => {
             println!("🔍 Searching for: \"{}\"...", query);
             let db = db::Database::init(&base_path).await?;
-            let search = search::SearchEngine::new(db.lancedb.clone()).await?;
+            let history = history::HistoryManager::new(std::sync::Arc::new(db), base_path.to_path_buf()).await?;
             
-            let results = search.search(query.clone(), 5).await?;
+            let filters = search::SearchFilters {
+                path: path.clone(),
+                since: since.as_deref().map(history::parse_time_spec).transpose()?,
+                until: until.as_deref().map(history::parse_time_spec).transpose()?,
+                session: match session {
+                    Some(prefix) => Some(history.get_session(prefix).await?.id),
+                    None => None,
+                },
+                branch: branch.clone(),
+                lang: lang.clone(),
+            };
+            let mode = search_mode(*exact, *regex);
+            let ranking = search_ranking(*decay, *first)?;
+            let results = history.search_engine().search(query.clone(), 5, mode, &filters, &ranking).await?;
+            // Notes carry none of the filtered attributes, so they only join unfiltered searches
+            let notes = if mode == search::SearchMode::Hybrid && filters.is_empty() {
+                history.search_notes(query, 3).await?
+            } else {
+                Vec::new()
+            };
             
-            if results.is_empty() {
+            if results.is_empty() && notes.is_empty() {
                 println!("🤷 No relevant history found.");
             } else {
-                println!("✨ Found {} relevant snapshots:", results.len());
+                println!("✨ Found {} relevant snippets:", results.len());
                 for (i, res) in results.iter().enumerate() {
-                    println!("\n[{}] File: {}", i + 1, res.file_path);
-                    println!("--- Snippet ---");
-                    let snippet: String = res.content.lines().take(5).collect::<Vec<_>>().join("\n");
-                    println!("{}...", snippet);
+                    let location = &res.shown().file_path;
+                    println!("\n[{}] {}:{}-{} ({})", i + 1, location, res.record.start_line, res.record.end_line, describe_hit(res));
+                    print_ranking(res);
+                    print_occurrences(res, 5);
+                    print_snippet(&res.record, 12);
+                }
+                if !notes.is_empty() {
+                    println!("\n📝 Related notes:");
+                    for note in &notes {
+                        println!("\n[{} {}]", note.target_kind, &note.target_id[..7]);
+                        println!("{}", note.body);
+                    }
                 }
             }
             Ok(())
@@ -157,20 +633,102 @@ async fn main() -> Result<()> {
             println!("⏪ Restoring {}...", file);
             let db = db::Database::init(&base_path).await?;
             let history = history::HistoryManager::new(std::sync::Arc::new(db), base_path.to_path_buf()).await?;
-            
-            // Safety: Snapshot the current state before overwriting
-            let current_path = if PathBuf::from(file).is_absolute() {
-                PathBuf::from(file)
-            } else {
-                base_path.join(file)
+
+            let plan = history.plan_restore(file, snapshot.clone()).await?;
+            if plan.files.is_empty() {
+                println!("✅ {} already matches that version.", file);
+                return Ok(());
+            }
+            history.start_session("restore", None).await?;
+            let applied = history.apply_restore(&plan).await;
+            history.end_session().await?;
+            applied?;
+            println!("✅ Restore complete. Run `stasher undo` to put the previous version back.");
+            Ok(())
+        }
+        Commands::Revert { snapshot, hunk } => {
+            use colored::Colorize;
+            let db = db::Database::init(&base_path).await?;
+            let history = history::HistoryManager::new(std::sync::Arc::new(db), base_path.to_path_buf()).await?;
+
+            history.start_session("revert", None).await?;
+            let outcome = history.revert(snapshot, *hunk).await;
+            history.end_session().await?;
+            let outcome = outcome?;
+
+            let what = match hunk {
+                Some(n) => format!("hunk {} of {}", n, outcome.hunks),
+                None => format!("{} hunks", outcome.hunks),
             };
-            
-            if current_path.exists() {
-                let _ = history.record_change(current_path).await;
+            if outcome.unchanged {
+                println!("✅ {} already doesn't contain that change ({}).", outcome.file_path, what);
+            } else if outcome.conflicts > 0 {
+                println!("⚠️ Reverted {} in {} with {} conflicts.", what, outcome.file_path.bold(), outcome.conflicts.to_string().red());
+                println!("   Resolve the <<<<<<< / >>>>>>> sections, or run `stasher undo` to put the previous version back.");
+            } else {
+                println!("↩️  Reverted {} in {}. Run `stasher undo` to put it back.", what, outcome.file_path.bold());
+            }
+            Ok(())
+        }
+        Commands::Pick { snapshot, side_by_side } => {
+            use colored::Colorize;
+            use std::io::Write;
+            let db = db::Database::init(&base_path).await?;
+            let history = history::HistoryManager::new(std::sync::Arc::new(db), base_path.to_path_buf()).await?;
+            let pick = history.plan_pick(snapshot).await?;
+
+            if pick.hunks.is_empty() {
+                println!("✅ {} already matches snapshot {}.", pick.file_path, snapshot);
+                return Ok(());
             }
+            println!("🍒 {} hunks differ between {} and snapshot {}.", pick.hunks.len(), pick.file_path.bold(), snapshot.cyan());
+            println!("{}", "   - lines are what you have now, + lines are what the snapshot had".dimmed());
 
-            history.restore_file(&file, snapshot.clone()).await?;
-            println!("✅ Restore complete.");
+            let style = diff::DiffStyle { side_by_side: *side_by_side, ..Default::default() };
+            let (working, historical) = (pick.working_lines(), pick.historical_lines());
+            let mut accepted = vec![false; pick.hunks.len()];
+            let mut rest = None;
+            for (i, hunk) in pick.hunks.iter().enumerate() {
+                if let Some(all) = rest {
+                    accepted[i] = all;
+                    continue;
+                }
+                println!();
+                diff::print_hunk(&working, &historical, hunk, &style);
+                loop {
+                    print!("{}", format!("({}/{}) Bring back this hunk [y,n,a,d,q,?]? ", i + 1, pick.hunks.len()).bold());
+                    std::io::stdout().flush()?;
+                    let mut answer = String::new();
+                    if std::io::stdin().read_line(&mut answer)? == 0 {
+                        answer = "q".to_string();
+                    }
+                    match answer.trim() {
+                        "y" => accepted[i] = true,
+                        "n" => {}
+                        "a" => {
+                            accepted[i] = true;
+                            rest = Some(true);
+                        }
+                        "d" | "q" => rest = Some(false),
+                        _ => {
+                            println!("y - bring back this hunk\nn - keep the current lines\na - this hunk and all later ones\nd - none of the remaining hunks\nq - stop, keeping the choices made so far");
+                            continue;
+                        }
+                    }
+                    break;
+                }
+            }
+
+            let count = accepted.iter().filter(|a| **a).count();
+            if count == 0 {
+                println!("🤷 No hunks picked; {} is unchanged.", pick.file_path);
+                return Ok(());
+            }
+            history.start_session("pick", None).await?;
+            let applied = history.apply_pick(&pick, &accepted).await;
+            history.end_session().await?;
+            applied?;
+            println!("✅ Brought back {} of {} hunks into {}. Run `stasher undo` to put it back.", count, pick.hunks.len(), pick.file_path.bold());
             Ok(())
         }
         Commands::Status => {
@@ -184,7 +742,24 @@ async fn main() -> Result<()> {
             
             println!("{:<20} {}", "Total Snapshots:".bold(), stats.total_snapshots.to_string().cyan());
             println!("{:<20} {}", "Total Sessions:".bold(), stats.total_sessions.to_string().cyan());
-            println!("{:<20} {}", "Indexed Files:".bold(), stats.indexed_count.to_string().green());
+            println!("{:<20} {}", "Indexed Chunks:".bold(), stats.indexed_count.to_string().green());
+            if stats.semantic_search {
+                println!("{:<20} {}", "Search:".bold(), "semantic + keyword".green());
+            } else {
+                println!("{:<20} {}", "Search:".bold(), "keyword only (no embedding model)".yellow());
+            }
+            if stats.vectors_incomplete {
+                println!("{:<20} {}", "".bold(), "some history lacks vectors; run `stasher reindex`".yellow());
+            }
+            if let Some(index) = &stats.vector_index {
+                let state = match index.indexed_rows {
+                    Some(indexed) if index.stale => format!("IVF-PQ, {} indexed, compacting after prune", indexed).yellow(),
+                    Some(indexed) => format!("IVF-PQ, {} indexed, {} pending", indexed, index.unindexed_rows).green(),
+                    None if index.rows >= index.threshold => "brute force, the daemon will build an index".yellow(),
+                    None => format!("brute force ({} of {} chunks before indexing)", index.rows, index.threshold).normal(),
+                };
+                println!("{:<20} {}", "Vector Index:".bold(), state);
+            }
             
             let objects_mb = stats.objects_size as f64 / 1_048_576.0;
             let total_mb = stats.total_size as f64 / 1_048_576.0;
@@ -212,6 +787,9 @@ async fn main() -> Result<()> {
             let history = history::HistoryManager::new(std::sync::Arc::new(db), base_path.to_path_buf()).await?;
             
             let snapshots = history.list_snapshots(file).await?;
+            let ids: Vec<String> = snapshots.iter().map(|s| s.id.clone()).collect();
+            let tags = history.snapshot_tags(&ids).await?;
+            let notes = history.notes_for(history::NoteTarget::Snapshot, &ids).await?;
             
             if snapshots.is_empty() {
                 println!("🤷 No history found for this file.");
@@ -222,24 +800,25 @@ async fn main() -> Result<()> {
                         println!("      {}", format!("⤴️ moved from {}", snap.file_path).italic().dimmed());
                         current_display_path = snap.file_path.clone();
                     }
-                    let dt = chrono::Utc::now().timestamp_millis() - snap.timestamp;
-                    let ago = if dt < 60000 {
-                        format!("{}s ago", dt / 1000)
-                    } else if dt < 3600000 {
-                        format!("{}m ago", dt / 60000)
-                    } else {
-                        format!("{}h ago", dt / 3600000)
-                    };
+                    let ago = format_ago(snap.timestamp);
+
+                    let tag_list = tags.get(&snap.id)
+                        .map(|names| format!(" 🏷️  {}", names.join(", ")).magenta().to_string())
+                        .unwrap_or_default();
 
                     println!(
-                        "[{}] {} | {}{} {}{}",
+                        "[{}] {} | {}{} {}{}{}",
                         &snap.id[..7].bright_white().bold(),
                         ago.yellow(),
                         "+".green(),
                         snap.lines_added.to_string().green(),
                         "-".red(),
-                        snap.lines_removed.to_string().red()
+                        snap.lines_removed.to_string().red(),
+                        tag_list
                     );
+                    for note in notes.get(&snap.id).into_iter().flatten() {
+                        print_note(note);
+                    }
                 }
             }
             Ok(())
@@ -255,25 +834,124 @@ async fn main() -> Result<()> {
             println!("   - {} unused objects deleted from disk", deleted_objs);
             Ok(())
         }
-        Commands::Diff { snapshot } => {
+        Commands::Model { action: ModelAction::Install { from, model } } => {
+            let config = config::Config::load(&base_path)?;
+            let model = model.clone().unwrap_or_else(|| config.embedding_model.clone());
+            println!("📥 Installing {} from {}...", model, from.display());
+
+            let installed = search::models::install(from, &model, &config.model_cache_dir()?)?;
+            println!("✅ Installed to {}", installed.display());
+            if model != config.embedding_model {
+                println!("💡 Run `stasher reindex --model {}` to switch this project to it.", model);
+            }
+            Ok(())
+        }
+        Commands::Reindex { model } => {
+            let db = db::Database::init(&base_path).await?;
+            let history = history::HistoryManager::new(std::sync::Arc::new(db), base_path.to_path_buf()).await?;
+            let target = match model {
+                Some(model) => model.clone(),
+                None => config::Config::load(&base_path)?.embedding_model,
+            };
+            println!("🔄 Rebuilding search index with {}...", target);
+
+            let stats = history.reindex(model.as_deref()).await?;
+            println!("✅ Search index rebuilt:");
+            println!("   - {} distinct file versions and {} changes embedded", stats.contents, stats.changes);
+            println!("   - {} chunks and {} notes indexed", stats.chunks, stats.notes);
+            if stats.missing_objects > 0 {
+                println!("   - ⚠️ {} versions skipped because their objects are missing", stats.missing_objects);
+            }
+            Ok(())
+        }
+        Commands::Diff { args, working, at, side_by_side, ignore_whitespace, paths } => {
             use colored::Colorize;
             let db = db::Database::init(&base_path).await?;
             let history = history::HistoryManager::new(std::sync::Arc::new(db), base_path.to_path_buf()).await?;
-            
-            let diff = history.get_snapshot_diff(snapshot).await?;
-            println!("📑 Diff for snapshot {}:", snapshot.cyan());
-            
-            for line in diff.lines() {
-                if line.starts_with('+') && !line.starts_with("+++") {
-                    println!("{}", line.green());
-                } else if line.starts_with('-') && !line.starts_with("---") {
-                    println!("{}", line.red());
-                } else if line.starts_with("@@") {
-                    println!("{}", line.blue());
-                } else {
-                    println!("{}", line);
+
+            let style = diff::DiffStyle { side_by_side: *side_by_side, ignore_whitespace: *ignore_whitespace };
+            let (mut sides, mut limit_to) = split_diff_args(&history, args).await?;
+            limit_to.extend(paths.iter().cloned());
+            let paths = &limit_to;
+            if *working && sides.len() > 1 {
+                anyhow::bail!("--working compares one snapshot, tag or time with the files on disk; got {}", sides.len());
+            }
+            for time in at {
+                sides.push((time.clone(), history::DiffSide::Tree(history.resolve_tree(time).await?)));
+            }
+
+            let (old, new) = match (sides.len(), *working) {
+                // The original form: what one snapshot changed, as recorded
+                (1, false) if at.is_empty() && paths.is_empty()
+                    && matches!(sides[0].1, history::DiffSide::Snapshot(_)) => {
+                    let snapshot = &sides[0].0;
+                    println!("📑 Diff for snapshot {}:", snapshot.cyan());
+                    match history.snapshot_versions(snapshot).await? {
+                        Some((path, before, after)) => {
+                            println!("📄 {}", path.bold());
+                            if !diff::print_diff(&before, &after, &style) {
+                                println!("✅ Only whitespace or formatting changed.");
+                            }
+                        }
+                        // The versions were pruned; the recorded patch is all that's left
+                        None => print_patch(&history.get_snapshot_diff(snapshot).await?),
+                    }
+                    return Ok(());
                 }
+                (1, _) => {
+                    let (label, side) = sides.remove(0);
+                    ((label, side), ("working copy".to_string(), history::DiffSide::Working))
+                }
+                (0, true) => (
+                    ("latest snapshots".to_string(), history::DiffSide::Tree(history.resolve_tree("now").await?)),
+                    ("working copy".to_string(), history::DiffSide::Working),
+                ),
+                (2, false) => {
+                    let new = sides.remove(1);
+                    (sides.remove(0), new)
+                }
+                _ => anyhow::bail!("Give one or two snapshots, tags or times (times can use --at), optionally with --working"),
+            };
+
+            println!("📑 Comparing {} → {}", old.0.cyan(), new.0.cyan());
+            let changes = history.compare(old.1, new.1, paths).await?;
+            if changes.is_empty() {
+                println!("✅ No differences.");
+                return Ok(());
             }
+            for change in &changes {
+                match change {
+                    history::FileChange::Modified { path, old, new } => {
+                        println!("\n📄 {}", path.bold());
+                        if !diff::print_diff(old, new, &style) {
+                            println!("{}", "   only whitespace or formatting changed".dimmed());
+                        }
+                    }
+                    history::FileChange::Added { path, content } => {
+                        println!("\n➕ {} {}", path.bold(), "(added)".green());
+                        diff::print_diff("", content, &style);
+                    }
+                    history::FileChange::Removed { path, content } => {
+                        println!("\n➖ {} {}", path.bold(), "(removed)".red());
+                        diff::print_diff(content, "", &style);
+                    }
+                    history::FileChange::Renamed { from, to, old, new, similarity } => {
+                        println!("\n🔀 {} → {} {}", from.bold(), to.bold(), format!("({:.0}% similar)", similarity * 100.0).yellow());
+                        if old != new {
+                            diff::print_diff(old, new, &style);
+                        }
+                    }
+                }
+            }
+            let count = |f: fn(&history::FileChange) -> bool| changes.iter().filter(|c| f(c)).count();
+            println!(
+                "\n{} files changed: {} modified, {} added, {} removed, {} renamed",
+                changes.len(),
+                count(|c| matches!(c, history::FileChange::Modified { .. })),
+                count(|c| matches!(c, history::FileChange::Added { .. })),
+                count(|c| matches!(c, history::FileChange::Removed { .. })),
+                count(|c| matches!(c, history::FileChange::Renamed { .. })),
+            );
             Ok(())
         }
         Commands::Projects => {
@@ -288,14 +966,7 @@ async fn main() -> Result<()> {
                 println!("🤷 No projects tracked yet. Run 'stasher init' in a project!");
             } else {
                 for p in projects {
-                    let dt = chrono::Utc::now().timestamp_millis() - p.last_active;
-                    let ago = if dt < 60000 {
-                        format!("{}s ago", dt / 1000)
-                    } else if dt < 3600000 {
-                        format!("{}m ago", dt / 60000)
-                    } else {
-                        format!("{}h ago", dt / 3600000)
-                    };
+                    let ago = format_ago(p.last_active);
 
                     println!(
                         "{} {} {}",
@@ -308,44 +979,393 @@ async fn main() -> Result<()> {
             }
             Ok(())
         }
-        Commands::GlobalAsk { query } => {
+        Commands::GlobalAsk { query, exact, regex, decay, first, timeout } => {
             use colored::Colorize;
             println!("🌐 Global Search: \"{}\"...", query.bold().cyan());
             let hub = hub::StasherHub::init().await?;
             let projects = hub.list_projects().await?;
-            
-            let mut all_results = Vec::new();
 
-            for project in projects {
-                let project_path = PathBuf::from(&project.path);
-                if !project_path.exists() { continue; }
+            let global_query = search::global::GlobalQuery::Search { query: query.clone(), mode: search_mode(*exact, *regex) };
+            let results = search::global::search_projects(
+                projects,
+                global_query,
+                10,
+                &search::SearchFilters::default(),
+                &search_ranking(*decay, *first)?,
+                std::time::Duration::from_secs(*timeout),
+            ).await?;
 
-                if let Ok(db) = db::Database::init(&project_path).await {
-                    let search = search::SearchEngine::new(db.lancedb.clone()).await?;
-                    if let Ok(results) = search.search(query.clone(), 3).await {
-                        for res in results {
-                            all_results.push((project.name.clone(), res));
-                        }
+            if results.hits.is_empty() {
+                println!("🤷 No relevant history found in any project.");
+            } else {
+                println!("✨ Found {} matches across your projects:", results.hits.len());
+                for found in &results.hits {
+                    let res = &found.hit;
+                    let location = format!("{}:{}-{}", res.shown().file_path, res.record.start_line, res.record.end_line);
+                    println!("\n{} | {} ({})", found.project.bold().magenta(), location.cyan(), describe_hit(res));
+                    print_ranking(res);
+                    print_occurrences(res, 3);
+                    print_snippet(&res.record, 6);
+                }
+            }
+            print_search_failures(&results.failures);
+            Ok(())
+        }
+        Commands::Similar { target, snapshot, global, path, decay, first } => {
+            use colored::Colorize;
+            let db = db::Database::init(&base_path).await?;
+            let history = history::HistoryManager::new(std::sync::Arc::new(db), base_path.to_path_buf()).await?;
+            let source = history.similar_source(target.as_deref(), snapshot.as_deref()).await?;
+            println!("🧬 Looking for code similar to {}...", source.label.bold().cyan());
+
+            let filters = search::SearchFilters { path: path.clone(), ..Default::default() };
+            let ranking = search_ranking(*decay, *first)?;
+
+            let mut results = Vec::new();
+            if *global {
+                let hub = hub::StasherHub::init().await?;
+                let found = search::global::search_projects(
+                    hub.list_projects().await?,
+                    search::global::GlobalQuery::Similar { code: source.text.clone() },
+                    10,
+                    &filters,
+                    &ranking,
+                    search::global::DEFAULT_TIMEOUT,
+                ).await?;
+                print_search_failures(&found.failures);
+                results.extend(found.hits.into_iter().map(|found| (Some(found.project), found.hit)));
+            } else {
+                let hits = history.search_engine().similar(&source.text, 5, &filters, &ranking).await?;
+                results.extend(hits.into_iter().map(|hit| (None, hit)));
+            }
+
+            if results.is_empty() {
+                println!("🤷 Nothing similar found.");
+            } else {
+                println!("✨ Found {} similar snippets:", results.len());
+                for (i, (project, res)) in results.iter().enumerate() {
+                    let location = format!("{}:{}-{}", res.shown().file_path, res.record.start_line, res.record.end_line);
+                    match project {
+                        Some(name) => println!("\n[{}] {} | {} ({})", i + 1, name.bold().magenta(), location.cyan(), describe_hit(res)),
+                        None => println!("\n[{}] {} ({})", i + 1, location, describe_hit(res)),
                     }
+                    print_ranking(res);
+                    print_snippet(&res.record, 12);
                 }
             }
+            Ok(())
+        }
+        Commands::Grep { pattern, path, since, until, ignore_case } => {
+            use anyhow::Context;
+            use colored::Colorize;
+            let regex = regex::RegexBuilder::new(pattern)
+                .case_insensitive(*ignore_case)
+                .build()
+                .context("Invalid regular expression")?;
+            let filters = search::SearchFilters {
+                path: path.clone(),
+                since: since.as_deref().map(history::parse_time_spec).transpose()?,
+                until: until.as_deref().map(history::parse_time_spec).transpose()?,
+                ..Default::default()
+            };
+            filters.validate()?;
 
-            if all_results.is_empty() {
-                println!("🤷 No relevant history found in any project.");
+            let db = db::Database::init(&base_path).await?;
+            let history = history::HistoryManager::without_model(std::sync::Arc::new(db), base_path.to_path_buf()).await?;
+            let (hits, stats) = history.grep(&regex, &filters).await?;
+
+            if hits.is_empty() {
+                println!("🤷 No version of any file matches. ({} versions scanned)", stats.objects);
             } else {
-                println!("✨ Found {} matches across your projects:", all_results.len());
-                for (proj_name, res) in all_results {
-                    println!("\n{} | File: {}", proj_name.bold().magenta(), res.file_path.cyan());
-                    let snippet: String = res.content.lines().take(3).collect::<Vec<_>>().join("\n");
-                    println!("--- Snippet ---");
-                    println!("{}...", snippet);
+                let files: std::collections::HashSet<&str> = hits.iter().map(|h| h.file_path.as_str()).collect();
+                println!("🔎 {} matching lines in {} files ({} versions scanned):", hits.len(), files.len(), stats.objects);
+                let mut current_file = "";
+                for hit in &hits {
+                    if hit.file_path != current_file {
+                        current_file = &hit.file_path;
+                        println!("\n{}", current_file.bold().cyan());
+                    }
+                    let presence = if hit.still_present { "present".green() } else { "gone".red() };
+                    println!("{} {}", format!("{:>5} |", hit.line_number).dimmed(), hit.line.trim_end());
+                    println!("{}{}", format!(
+                        "        first {} ({}) · last {} ({}) · {} snapshots · ",
+                        format_ago(hit.first_seen),
+                        &hit.first_snapshot[..7],
+                        format_ago(hit.last_seen),
+                        &hit.last_snapshot[..7],
+                        hit.snapshots
+                    ).dimmed(), presence);
                 }
             }
+            if stats.unreadable > 0 {
+                println!("\n⚠️ {} versions skipped because their objects are missing or not text", stats.unreadable);
+            }
+            Ok(())
+        }
+        Commands::Pickaxe { text, file } => {
+            use colored::Colorize;
+            let db = db::Database::init(&base_path).await?;
+            let history = history::HistoryManager::without_model(std::sync::Arc::new(db), base_path.to_path_buf()).await?;
+            let changes = history.pickaxe(text, file.as_deref()).await?;
+
+            if changes.is_empty() {
+                println!("🤷 \"{}\" never appeared in any recorded version.", text);
+                return Ok(());
+            }
+            println!("⛏️  {} snapshots changed how often \"{}\" occurs:", changes.len(), text.bold());
+            for change in &changes {
+                let movement = if change.before == 0 {
+                    "appeared".green()
+                } else if change.after == 0 {
+                    "disappeared".red()
+                } else {
+                    format!("{} → {}", change.before, change.after).yellow()
+                };
+                println!(
+                    "\n{} {} {} {}",
+                    change.snapshot_id[..7].yellow(),
+                    format_ago(change.timestamp).dimmed(),
+                    change.file_path.cyan(),
+                    movement
+                );
+                for hunk in &change.hunks {
+                    print_patch(hunk);
+                }
+            }
+            Ok(())
+        }
+        Commands::Blame { file } => {
+            use colored::Colorize;
+            let db = db::Database::init(&base_path).await?;
+            let history = history::HistoryManager::without_model(std::sync::Arc::new(db), base_path.to_path_buf()).await?;
+            let blame = history.blame(file).await?;
+
+            if !blame.from_working_copy {
+                println!("{}", format!("⚠️ {} is not on disk; showing its latest snapshot", file).yellow());
+            }
+            let current_path = blame.origins.last().map(|o| o.file_path.as_str()).unwrap_or_default();
+            let width = blame.lines.len().to_string().len();
+            for (i, (line, origin)) in blame.lines.iter().enumerate() {
+                let annotation = match origin.map(|o| &blame.origins[o]) {
+                    Some(origin) => {
+                        // Lines from before a move name the path they were written under
+                        let moved = if origin.file_path == current_path { String::new() } else { format!(" {}", origin.file_path) };
+                        format!("{} {} {:>8}{}", &origin.snapshot_id[..7], &origin.session_id[..7], format_ago(origin.timestamp), moved)
+                    }
+                    None => format!("{:<7} {:<7} {:>8}", "unsaved", "", ""),
+                };
+                println!("{} {} {}", annotation.dimmed(), format!("{:>width$} |", i + 1, width = width).dimmed(), line);
+            }
             Ok(())
         }
         Commands::Serve => {
             server::start_server().await?;
             Ok(())
         }
+        Commands::Tag { name, snapshot, all_files: _, delete } => {
+            use colored::Colorize;
+            let db = db::Database::init(&base_path).await?;
+            let history = history::HistoryManager::new(std::sync::Arc::new(db), base_path.to_path_buf()).await?;
+
+            let Some(name) = name else {
+                let tags = history.list_tags().await?;
+                if tags.is_empty() {
+                    println!("🤷 No tags yet. Create one with 'stasher tag <name>'.");
+                }
+                for tag in tags {
+                    let scope = if tag.all_files {
+                        format!("{} files", tag.snapshot_count)
+                    } else {
+                        "1 snapshot".to_string()
+                    };
+                    println!("🏷️  {} | {} | {}", tag.name.bold().magenta(), format_ago(tag.timestamp).yellow(), scope.dimmed());
+                }
+                return Ok(());
+            };
+
+            if *delete {
+                history.delete_tag(name).await?;
+                println!("🗑️  Deleted tag {}", name.magenta());
+                return Ok(());
+            }
+
+            let target = match snapshot {
+                Some(reference) => history::TagTarget::Snapshot(reference.clone()),
+                None => history::TagTarget::AllFiles,
+            };
+            let tag = history.create_tag(name, target).await?;
+            if tag.all_files {
+                println!("🏷️  Tagged {} files as {}", tag.snapshot_count, tag.name.bold().magenta());
+            } else {
+                println!("🏷️  Tagged snapshot as {}", tag.name.bold().magenta());
+            }
+            Ok(())
+        }
+        Commands::Checkout { target, paths } => {
+            println!("⏪ Checking out {}...", target);
+            let db = db::Database::init(&base_path).await?;
+            let history = history::HistoryManager::new(std::sync::Arc::new(db), base_path.to_path_buf()).await?;
+
+            let plan = history.plan_checkout(target, paths).await?;
+            history.start_session("checkout", None).await?;
+            let applied = history.apply_restore(&plan).await;
+            history.end_session().await?;
+            applied?;
+
+            println!("✅ Checkout complete:");
+            println!("   - {} files restored", plan.files.len());
+            println!("   - {} files already up to date", plan.unchanged);
+            if !plan.files.is_empty() {
+                println!("   Run `stasher undo` to put them back.");
+            }
+            Ok(())
+        }
+        Commands::Undo => {
+            let db = db::Database::init(&base_path).await?;
+            let history = history::HistoryManager::new(std::sync::Arc::new(db), base_path.to_path_buf()).await?;
+            let outcome = history.undo_last_restore().await?;
+            if outcome.interrupted {
+                println!("⚠️ That {} was interrupted; only the files it reached were put back.", outcome.command);
+            }
+            println!(
+                "↩️  Undid the {} of {} from {}: {} files put back.",
+                outcome.command,
+                outcome.reference,
+                format_ago(outcome.created_at),
+                outcome.files
+            );
+            Ok(())
+        }
+        Commands::Note { action } => {
+            let db = db::Database::init(&base_path).await?;
+            let history = history::HistoryManager::new(std::sync::Arc::new(db), base_path.to_path_buf()).await?;
+
+            match action {
+                NoteAction::Add { target, text, session, snapshot } => {
+                    let body = if text == "-" {
+                        std::io::read_to_string(std::io::stdin())?
+                    } else {
+                        text.clone()
+                    };
+                    let kind = if *session {
+                        Some(history::NoteTarget::Session)
+                    } else {
+                        snapshot.then_some(history::NoteTarget::Snapshot)
+                    };
+                    let note = history.add_note(target, kind, &body).await?;
+                    println!("📝 Added note {} to {} {}", &note.id[..7], note.target_kind, &note.target_id[..7]);
+                }
+                NoteAction::List { target, session } => {
+                    let (kind, target_id) = if *session {
+                        (history::NoteTarget::Session, history.get_session(target).await?.id)
+                    } else {
+                        (history::NoteTarget::Snapshot, history.resolve_snapshot(target, None).await?)
+                    };
+                    let notes = history.notes_for(kind, std::slice::from_ref(&target_id)).await?;
+                    match notes.get(&target_id) {
+                        Some(notes) => notes.iter().for_each(print_note),
+                        None => println!("🤷 No notes on {}.", target),
+                    }
+                }
+                NoteAction::Remove { id } => {
+                    history.delete_note(id).await?;
+                    println!("🗑️  Note removed.");
+                }
+            }
+            Ok(())
+        }
+        Commands::Sessions { action } => {
+            use colored::Colorize;
+            let db = db::Database::init(&base_path).await?;
+            let history = history::HistoryManager::new(std::sync::Arc::new(db), base_path.to_path_buf()).await?;
+
+            match action {
+                SessionAction::List { limit } => {
+                    let sessions = history.list_sessions(*limit).await?;
+                    if sessions.is_empty() {
+                        println!("🤷 No sessions recorded yet.");
+                    }
+                    for session in sessions {
+                        print_session_line(&session);
+                    }
+                }
+                SessionAction::Show { id } => {
+                    let session = history.get_session(id).await?;
+                    print_session_line(&session);
+                    let notes = history.notes_for(history::NoteTarget::Session, std::slice::from_ref(&session.id)).await?;
+                    for note in notes.get(&session.id).into_iter().flatten() {
+                        print_note(note);
+                    }
+                    println!();
+                    for snap in history.session_snapshots(&session.id).await? {
+                        let time = chrono::DateTime::from_timestamp_millis(snap.timestamp)
+                            .map(|t| t.with_timezone(&chrono::Local).format("%H:%M:%S").to_string())
+                            .unwrap_or_default();
+                        println!(
+                            "  [{}] {} {} | {}{} {}{}",
+                            &snap.id[..7].bright_white().bold(),
+                            time.yellow(),
+                            snap.file_path.cyan(),
+                            "+".green(),
+                            snap.lines_added.to_string().green(),
+                            "-".red(),
+                            snap.lines_removed.to_string().red()
+                        );
+                    }
+                }
+                SessionAction::Label { id, label } => {
+                    let session_id = history.label_session(id, label).await?;
+                    println!("🏷️  Labelled session {} as \"{}\"", &session_id[..7], label);
+                }
+            }
+            Ok(())
+        }
+        Commands::Bundle { action: BundleAction::Create { file, path, since, until, vectors } } => {
+            let filter = bundle::BundleFilter {
+                path_glob: path.clone(),
+                since: since.as_deref().map(history::parse_time_spec).transpose()?,
+                until: until.as_deref().map(history::parse_time_spec).transpose()?,
+                include_vectors: *vectors,
+            };
+
+            println!("📦 Packing history into {}...", file.display());
+            let db = db::Database::init(&base_path).await?;
+            let bundle = bundle::create_bundle(&db, &base_path, &filter).await?;
+            bundle::write_bundle(&bundle, file)?;
+
+            println!("✅ Bundle created:");
+            println!("   - {} sessions", bundle.sessions.len());
+            println!("   - {} snapshots", bundle.snapshots.len());
+            println!("   - {} objects", bundle.objects.len());
+            println!("   - {} tags and {} notes", bundle.tags.len(), bundle.notes.len());
+            if let Some(vectors) = &bundle.vectors {
+                println!("   - {} vectors", vectors.len());
+            }
+            Ok(())
+        }
+        Commands::Bundle { action: BundleAction::Import { file } } => {
+            println!("📦 Importing {} into {}...", file.display(), base_path.display());
+            let bundle = bundle::read_bundle(file)?;
+
+            let db = db::Database::init(&base_path).await?;
+            let stats = bundle::import_bundle(&db, &base_path, bundle).await?;
+
+            println!("✅ Import complete:");
+            println!("   - {} sessions", stats.sessions);
+            println!("   - {} snapshots", stats.snapshots);
+            println!("   - {} objects", stats.objects);
+            println!("   - {} vectors", stats.vectors);
+            println!("   - {} tags and {} notes", stats.tags, stats.notes);
+            if !stats.tag_conflicts.is_empty() {
+                println!("   ⚠️  Skipped tags already defined here: {}", stats.tag_conflicts.join(", "));
+            }
+            if stats.keyword_only > 0 {
+                println!("   ℹ️  {} chunks came without usable vectors and are searchable by keyword only. Run `stasher reindex` for semantic search.", stats.keyword_only);
+            }
+
+            let hub = hub::StasherHub::init().await?;
+            hub.register_project(&base_path).await?;
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,158 @@
+//! Approximate nearest-neighbour index over the chunk table.
+//!
+//! Small histories are searched by brute force, which is exact and fast enough. Once a table
+//! passes the configured row count the daemon builds an IVF-PQ index, folds new rows into it as
+//! they pile up, compacts it after a prune, and retrains it when the table has doubled since the
+//! index was built, because the clusters it learned no longer fit the data.
+
+use super::SearchEngine;
+use anyhow::Result;
+use lancedb::index::vector::IvfPqIndexBuilder;
+use lancedb::index::Index;
+use lancedb::table::{OptimizeAction, OptimizeOptions};
+use lancedb::Table;
+
+const VECTOR_INDEX: &str = "vector_idx";
+/// Product quantization needs this many rows to train on.
+const MIN_TRAINING_ROWS: usize = 256;
+/// Set by a prune that deleted vectors, cleared once the index has been compacted.
+const STALE_SETTING: &str = "vector_index_stale";
+
+/// What `status` reports about a chunk table's index.
+pub struct VectorIndexStatus {
+    pub rows: usize,
+    pub threshold: usize,
+    /// `None` while the table is searched by brute force.
+    pub indexed_rows: Option<usize>,
+    /// Rows added since the index was last updated; searched by brute force alongside it.
+    pub unindexed_rows: usize,
+    /// A prune deleted rows the index still covers.
+    pub stale: bool,
+}
+
+/// What `maintain_vector_index` did.
+pub enum Maintenance {
+    Unneeded,
+    Built { rows: usize },
+    Retrained { rows: usize },
+    Optimized { rows: usize },
+}
+
+impl SearchEngine {
+    pub async fn vector_index_status(&self, threshold: usize) -> Result<Option<VectorIndexStatus>> {
+        let Some(table) = self.open_chunks().await? else {
+            return Ok(None);
+        };
+        Ok(Some(self.table_status(&table, threshold).await?))
+    }
+
+    async fn table_status(&self, table: &Table, threshold: usize) -> Result<VectorIndexStatus> {
+        let rows = table.count_rows(None).await?;
+        let stats = table.index_stats(VECTOR_INDEX).await?;
+        Ok(VectorIndexStatus {
+            rows,
+            threshold: threshold.max(MIN_TRAINING_ROWS),
+            indexed_rows: stats.as_ref().map(|s| s.num_indexed_rows),
+            unindexed_rows: stats.map(|s| s.num_unindexed_rows).unwrap_or(rows),
+            stale: self.setting(STALE_SETTING).await?.is_some(),
+        })
+    }
+
+    /// Builds, updates or retrains the index as the table has changed. Cheap when there's
+    /// nothing to do, so the daemon calls it periodically.
+    pub async fn maintain_vector_index(&self, threshold: usize) -> Result<Maintenance> {
+        let Some(table) = self.open_chunks().await? else {
+            return Ok(Maintenance::Unneeded);
+        };
+        let status = self.table_status(&table, threshold).await?;
+        let rows = status.rows;
+
+        let Some(indexed) = status.indexed_rows else {
+            if rows < status.threshold {
+                return Ok(Maintenance::Unneeded);
+            }
+            self.build_vector_index(&table, rows).await?;
+            return Ok(Maintenance::Built { rows });
+        };
+
+        let built_with: usize = self.setting(&self.trained_rows_setting()).await?
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(indexed);
+        if rows >= built_with * 2 {
+            self.build_vector_index(&table, rows).await?;
+            return Ok(Maintenance::Retrained { rows });
+        }
+
+        if status.stale {
+            // Compaction drops deleted rows and rewrites the index over what's left
+            table.optimize(OptimizeAction::All).await?;
+            self.clear_setting(STALE_SETTING).await?;
+            return Ok(Maintenance::Optimized { rows });
+        }
+        if status.unindexed_rows > (indexed / 10).max(MIN_TRAINING_ROWS) {
+            table.optimize(OptimizeAction::Index(OptimizeOptions::default())).await?;
+            return Ok(Maintenance::Optimized { rows });
+        }
+        Ok(Maintenance::Unneeded)
+    }
+
+    /// Deletes the vectors stored under `keys`, leaving the index to be compacted later.
+    pub async fn remove_vectors(&self, keys: &[String]) -> Result<()> {
+        let Some(table) = self.open_chunks().await? else {
+            return Ok(());
+        };
+        for batch in keys.chunks(500) {
+            let list: Vec<String> = batch.iter().map(|k| format!("'{}'", k.replace('\'', "''"))).collect();
+            table.delete(&format!("key IN ({})", list.join(", "))).await?;
+        }
+        if !keys.is_empty() && table.index_stats(VECTOR_INDEX).await?.is_some() {
+            sqlx::query("INSERT OR IGNORE INTO settings (key, value) VALUES (?, '1')")
+                .bind(STALE_SETTING)
+                .execute(&self.sqlite)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn build_vector_index(&self, table: &Table, rows: usize) -> Result<()> {
+        table.create_index(&["vector"], Index::IvfPq(IvfPqIndexBuilder::default()))
+            .name(VECTOR_INDEX.to_string())
+            .replace(true)
+            .execute()
+            .await?;
+        sqlx::query("INSERT INTO settings (key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value")
+            .bind(self.trained_rows_setting())
+            .bind(rows.to_string())
+            .execute(&self.sqlite)
+            .await?;
+        self.clear_setting(STALE_SETTING).await
+    }
+
+    /// Row count when the index was last trained, kept per generation.
+    fn trained_rows_setting(&self) -> String {
+        format!("vector_index_rows.{}", self.index.chunks_table())
+    }
+
+    async fn open_chunks(&self) -> Result<Option<Table>> {
+        let table_names: Vec<String> = self.lancedb.table_names().execute().await?;
+        if !table_names.contains(&self.index.chunks_table()) {
+            return Ok(None);
+        }
+        Ok(Some(self.lancedb.open_table(self.index.chunks_table()).execute().await?))
+    }
+
+    async fn setting(&self, key: &str) -> Result<Option<String>> {
+        Ok(sqlx::query_scalar("SELECT value FROM settings WHERE key = ?")
+            .bind(key)
+            .fetch_optional(&self.sqlite)
+            .await?)
+    }
+
+    async fn clear_setting(&self, key: &str) -> Result<()> {
+        sqlx::query("DELETE FROM settings WHERE key = ?")
+            .bind(key)
+            .execute(&self.sqlite)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,360 @@
+//! Splits files into the units we embed for search.
+//!
+//! Known languages are cut at top-level items (functions, impls, classes, ...) by tracking bracket
+//! depth and indentation, which keeps each unit intact without a full parser. Everything else,
+//! and any unit that is still too long, falls back to overlapping line windows.
+
+/// Units shorter than this are merged with their small neighbours (imports, constants).
+const MIN_CHUNK_LINES: usize = 6;
+/// Units longer than this are split into windows.
+const MAX_CHUNK_LINES: usize = 80;
+const WINDOW_LINES: usize = 60;
+const WINDOW_OVERLAP: usize = 10;
+
+/// Whether a chunk is a slice of a saved file or a run of lines a save added or removed.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ChunkKind {
+    Content,
+    Added,
+    Removed,
+}
+
+impl ChunkKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChunkKind::Content => "content",
+            ChunkKind::Added => "added",
+            ChunkKind::Removed => "removed",
+        }
+    }
+}
+
+pub struct Chunk {
+    /// 1-based, inclusive.
+    pub start_line: usize,
+    /// 1-based, inclusive.
+    pub end_line: usize,
+    pub text: String,
+}
+
+/// Language names used in search filters, with the file extensions that belong to each.
+pub const LANGUAGES: &[(&str, &[&str])] = &[
+    ("rust", &["rs"]),
+    ("python", &["py", "pyi"]),
+    ("javascript", &["js", "jsx", "mjs", "cjs"]),
+    ("typescript", &["ts", "tsx", "mts", "cts"]),
+    ("go", &["go"]),
+    ("java", &["java"]),
+    ("kotlin", &["kt", "kts"]),
+    ("swift", &["swift"]),
+    ("c", &["c", "h"]),
+    ("cpp", &["cc", "cpp", "cxx", "hpp", "hh"]),
+    ("csharp", &["cs"]),
+    ("php", &["php"]),
+    ("ruby", &["rb"]),
+    ("scala", &["scala"]),
+];
+
+/// Maps a file extension to the language name used in search filters and chunking.
+pub fn language_for(file_path: &str) -> Option<&'static str> {
+    let ext = file_path.rsplit_once('.')?.1.to_ascii_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|(_, extensions)| extensions.contains(&ext.as_str()))
+        .map(|(lang, _)| *lang)
+}
+
+/// File extensions for a language name, if it's one we know.
+pub fn extensions_for(lang: &str) -> Option<&'static [&'static str]> {
+    LANGUAGES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(lang))
+        .map(|(_, extensions)| *extensions)
+}
+
+pub fn chunk_file(file_path: &str, content: &str) -> Vec<Chunk> {
+    let lines: Vec<&str> = content.lines().collect();
+    if lines.is_empty() {
+        return Vec::new();
+    }
+
+    let units = match language_for(file_path) {
+        Some("ruby") | None => vec![(0, lines.len())],
+        Some(lang) => top_level_units(&lines, lang == "python"),
+    };
+
+    let mut chunks = Vec::new();
+    for (start, end) in merge_small(&lines, units) {
+        if end - start > MAX_CHUNK_LINES {
+            windows(&lines, start, end, &mut chunks);
+        } else {
+            chunks.push(make_chunk(&lines, start, end));
+        }
+    }
+    chunks
+}
+
+/// Pulls the removed and added lines of each hunk out of a unified diff. Removed lines are
+/// numbered against the old file, added lines against the new one. Blank-only runs are dropped.
+pub fn diff_chunks(patch: &str) -> Vec<(ChunkKind, Chunk)> {
+    let mut chunks = Vec::new();
+    let mut removed: Vec<(usize, &str)> = Vec::new();
+    let mut added: Vec<(usize, &str)> = Vec::new();
+    let (mut old_line, mut new_line) = (0, 0);
+    let mut in_hunk = false;
+
+    for line in patch.lines() {
+        if let Some(header) = line.strip_prefix("@@ ") {
+            flush_hunk(&mut removed, &mut added, &mut chunks);
+            (old_line, new_line) = parse_hunk_header(header);
+            in_hunk = true;
+        } else if !in_hunk || line.starts_with('\\') {
+            // File headers, or "\ No newline at end of file"; a removed "-- x" line is content
+            continue;
+        } else if let Some(text) = line.strip_prefix('-') {
+            removed.push((old_line, text));
+            old_line += 1;
+        } else if let Some(text) = line.strip_prefix('+') {
+            added.push((new_line, text));
+            new_line += 1;
+        } else {
+            old_line += 1;
+            new_line += 1;
+        }
+    }
+    flush_hunk(&mut removed, &mut added, &mut chunks);
+    chunks
+}
+
+fn parse_hunk_header(header: &str) -> (usize, usize) {
+    let start_of = |prefix: char| {
+        header
+            .split_whitespace()
+            .find_map(|part| part.strip_prefix(prefix))
+            .and_then(|range| range.split(',').next())
+            .and_then(|n| n.parse::<usize>().ok())
+            .unwrap_or(1)
+    };
+    (start_of('-'), start_of('+'))
+}
+
+fn flush_hunk<'a>(removed: &mut Vec<(usize, &'a str)>, added: &mut Vec<(usize, &'a str)>, chunks: &mut Vec<(ChunkKind, Chunk)>) {
+    for (kind, lines) in [(ChunkKind::Removed, removed), (ChunkKind::Added, added)] {
+        if lines.iter().any(|(_, text)| !text.trim().is_empty()) {
+            for piece in lines.chunks(WINDOW_LINES) {
+                chunks.push((kind, Chunk {
+                    start_line: piece[0].0,
+                    end_line: piece[piece.len() - 1].0,
+                    text: piece.iter().map(|(_, text)| *text).collect::<Vec<_>>().join("\n"),
+                }));
+            }
+        }
+        lines.clear();
+    }
+}
+
+/// Half-open line ranges, one per top-level item. A new item starts at any non-blank line in
+/// column 0 that sits outside all brackets and doesn't close one.
+fn top_level_units(lines: &[&str], hash_comments: bool) -> Vec<(usize, usize)> {
+    let mut starts = vec![0];
+    let mut depth: i64 = 0;
+    let mut in_block_comment = false;
+
+    for (i, line) in lines.iter().enumerate() {
+        let starts_item = depth == 0
+            && !in_block_comment
+            && !line.is_empty()
+            && !line.starts_with(char::is_whitespace)
+            && !line.starts_with(['}', ')', ']']);
+        if starts_item && i > 0 {
+            starts.push(i);
+        }
+        depth = (depth + bracket_delta(line, hash_comments, &mut in_block_comment)).max(0);
+    }
+
+    // Comments, attributes and decorators belong to the item below them
+    let mut units: Vec<(usize, usize)> = Vec::new();
+    let mut pending_start: Option<usize> = None;
+    for (n, &start) in starts.iter().enumerate() {
+        let end = starts.get(n + 1).copied().unwrap_or(lines.len());
+        let start = pending_start.take().unwrap_or(start);
+        if end < lines.len() && lines[start..end].iter().all(|l| is_preamble(l)) {
+            pending_start = Some(start);
+        } else {
+            units.push((start, end));
+        }
+    }
+    if let Some(start) = pending_start {
+        units.push((start, lines.len()));
+    }
+    units
+}
+
+fn is_preamble(line: &str) -> bool {
+    let line = line.trim();
+    line.is_empty()
+        || line.starts_with("//")
+        || line.starts_with("/*")
+        || line.starts_with('*')
+        || line.starts_with('#')
+        || line.starts_with('@')
+}
+
+/// Net change in bracket depth over a line, skipping string literals and comments.
+fn bracket_delta(line: &str, hash_comments: bool, in_block_comment: &mut bool) -> i64 {
+    let mut delta = 0;
+    let mut chars = line.chars().peekable();
+    let mut quote: Option<char> = None;
+
+    while let Some(c) = chars.next() {
+        if *in_block_comment {
+            if c == '*' && chars.peek() == Some(&'/') {
+                chars.next();
+                *in_block_comment = false;
+            }
+            continue;
+        }
+        if let Some(q) = quote {
+            if c == '\\' {
+                chars.next();
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '"' | '`' => quote = Some(c),
+            // Single quotes are Rust lifetimes as often as they are literals; only treat
+            // them as quotes when they look like a closed char/string literal.
+            '\'' if line.matches('\'').count().is_multiple_of(2) => quote = Some(c),
+            '/' if chars.peek() == Some(&'/') => break,
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                *in_block_comment = true;
+            }
+            '#' if hash_comments => break,
+            '{' | '(' | '[' => delta += 1,
+            '}' | ')' | ']' => delta -= 1,
+            _ => {}
+        }
+    }
+    delta
+}
+
+/// Joins runs of tiny units (imports, one-line constants) so each chunk carries some context.
+fn merge_small(lines: &[&str], units: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    let is_small = |(start, end): (usize, usize)| {
+        lines[start..end].iter().filter(|l| !l.trim().is_empty()).count() < MIN_CHUNK_LINES
+    };
+
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for unit in units {
+        match merged.last_mut() {
+            Some(last) if is_small(*last) && is_small(unit) => last.1 = unit.1,
+            _ => merged.push(unit),
+        }
+    }
+    merged
+}
+
+fn windows(lines: &[&str], start: usize, end: usize, chunks: &mut Vec<Chunk>) {
+    let mut window_start = start;
+    loop {
+        let window_end = (window_start + WINDOW_LINES).min(end);
+        chunks.push(make_chunk(lines, window_start, window_end));
+        if window_end == end {
+            break;
+        }
+        window_start = window_end - WINDOW_OVERLAP;
+    }
+}
+
+fn make_chunk(lines: &[&str], start: usize, end: usize) -> Chunk {
+    Chunk {
+        start_line: start + 1,
+        end_line: end,
+        text: lines[start..end].join("\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_chunks, top_level_units};
+
+    fn summarize(patch: &str) -> Vec<(&'static str, usize, usize, String)> {
+        diff_chunks(patch)
+            .into_iter()
+            .map(|(kind, chunk)| (kind.as_str(), chunk.start_line, chunk.end_line, chunk.text))
+            .collect()
+    }
+
+    #[test]
+    fn diff_chunks_numbers_lines_per_side() {
+        let patch = "--- a.rs\n+++ a.rs\n@@ -1,3 +1,3 @@\n fn a() {}\n-fn b() {}\n+fn c() {}\n fn d() {}\n";
+        assert_eq!(summarize(patch), vec![
+            ("removed", 2, 2, "fn b() {}".to_string()),
+            ("added", 2, 2, "fn c() {}".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn diff_chunks_keeps_header_lookalikes_inside_hunks() {
+        let patch = "@@ -1,2 +1,2 @@\n--- old comment\n+++ new comment\n SELECT 1;\n\\ No newline at end of file\n";
+        assert_eq!(summarize(patch), vec![
+            ("removed", 1, 1, "-- old comment".to_string()),
+            ("added", 1, 1, "++ new comment".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn diff_chunks_restarts_numbering_at_each_hunk() {
+        let patch = "@@ -10,2 +10,3 @@\n keep\n+added\n keep\n@@ -20,2 +21,1 @@\n keep\n-gone\n";
+        assert_eq!(summarize(patch), vec![
+            ("added", 11, 11, "added".to_string()),
+            ("removed", 21, 21, "gone".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn diff_chunks_drops_blank_runs() {
+        assert!(summarize("@@ -1,1 +1,3 @@\n x\n+\n+   \n").is_empty());
+    }
+
+    #[test]
+    fn top_level_units_attach_preamble_to_the_next_item() {
+        let lines = [
+            "use std::fmt;",
+            "",
+            "/// Doc",
+            "#[derive(Debug)]",
+            "struct A {",
+            "    x: i32,",
+            "}",
+            "",
+            "fn f() {",
+            "    let s = \"{\";",
+            "}",
+        ];
+        assert_eq!(top_level_units(&lines, false), vec![(0, 2), (2, 8), (8, 11)]);
+    }
+
+    #[test]
+    fn top_level_units_handle_decorators_and_hash_comments() {
+        let lines = [
+            "import os",
+            "",
+            "@decorator",
+            "def f():",
+            "    return {",
+            "        'a': 1,",
+            "    }",
+            "class B:",
+            "    pass",
+        ];
+        assert_eq!(top_level_units(&lines, true), vec![(0, 2), (2, 7), (7, 9)]);
+
+        let lines = ["x = 1  # {", "y = 2"];
+        assert_eq!(top_level_units(&lines, true), vec![(0, 1), (1, 2)]);
+        assert_eq!(top_level_units(&lines, false), vec![(0, 2)]);
+    }
+}
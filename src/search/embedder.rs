@@ -0,0 +1,170 @@
+//! Backends that turn text into vectors. `SearchEngine` only sees the `Embedder` trait, so the
+//! index doesn't care whether vectors come from an in-process model, a local server or a hash.
+
+use crate::config::{Config, EmbeddingBackend};
+use super::models;
+use anyhow::{Context, Result};
+use fastembed::TextEmbedding;
+use serde::Deserialize;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// Dimension of the hash embedder when the config doesn't set `embedding_dim`.
+const DEFAULT_HASH_DIM: usize = 256;
+
+/// Embedding blocks (on a model or on the network), so async callers run it on
+/// `tokio::task::spawn_blocking`.
+pub trait Embedder: Send + Sync {
+    /// Embeds each text into a vector of `dim()` floats, in order.
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>>;
+
+    /// Vector size. Backends that learn it from their first response report 0 until then.
+    fn dim(&self) -> usize;
+}
+
+/// Identifies the vector space of the configured backend. Recorded in index metadata, so vectors
+/// from different backends or models are never compared.
+pub fn model_id(config: &Config) -> String {
+    match config.embedding_backend {
+        EmbeddingBackend::FastEmbed => config.embedding_model.clone(),
+        EmbeddingBackend::Http => format!("http:{}", config.embedding_model),
+        EmbeddingBackend::Hash => format!("hash-{}", config.embedding_dim.unwrap_or(DEFAULT_HASH_DIM)),
+    }
+}
+
+/// Builds the configured backend. The error explains why it isn't usable.
+pub fn load(config: &Config) -> Result<Box<dyn Embedder>> {
+    Ok(match config.embedding_backend {
+        EmbeddingBackend::FastEmbed => Box::new(FastEmbedder::load(config)?),
+        EmbeddingBackend::Http => Box::new(HttpEmbedder::new(config)?),
+        EmbeddingBackend::Hash => Box::new(HashEmbedder::new(config.embedding_dim.unwrap_or(DEFAULT_HASH_DIM))),
+    })
+}
+
+/// An ONNX model run in-process by fastembed. The default.
+pub struct FastEmbedder {
+    model: Mutex<TextEmbedding>,
+    dim: usize,
+}
+
+impl FastEmbedder {
+    pub fn load(config: &Config) -> Result<Self> {
+        let model_code = &config.embedding_model;
+        let dim = TextEmbedding::get_model_info(&models::parse_model(model_code)?)?.dim;
+        Ok(Self {
+            model: Mutex::new(models::load(config, model_code)?),
+            dim,
+        })
+    }
+}
+
+impl Embedder for FastEmbedder {
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        let mut model = self.model.lock().map_err(|_| anyhow::anyhow!("Embedding model lock poisoned"))?;
+        model.embed(texts, None)
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+}
+
+/// A model served over HTTP on this machine, speaking Ollama's `/api/embed` protocol.
+pub struct HttpEmbedder {
+    agent: ureq::Agent,
+    endpoint: String,
+    model: String,
+    dim: OnceLock<usize>,
+}
+
+#[derive(Deserialize)]
+struct EmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+impl HttpEmbedder {
+    /// Targets `embedding_url`. The server is first contacted when something is embedded;
+    /// without `embedding_dim` in the config, the dimension is learned from that response.
+    pub fn new(config: &Config) -> Result<Self> {
+        let url = config.embedding_url.as_deref()
+            .context("The http embedding backend needs `embedding_url` in .stasher/config.json")?;
+        let dim = OnceLock::new();
+        if let Some(configured) = config.embedding_dim {
+            let _ = dim.set(configured);
+        }
+        Ok(Self {
+            agent: ureq::AgentBuilder::new().timeout(Duration::from_secs(60)).build(),
+            endpoint: format!("{}/api/embed", url.trim_end_matches('/')),
+            model: config.embedding_model.clone(),
+            dim,
+        })
+    }
+
+    fn request(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        let count = texts.len();
+        let response: EmbedResponse = self.agent.post(&self.endpoint)
+            .send_json(serde_json::json!({ "model": self.model, "input": texts }))
+            .context(format!("Embedding request to {} failed", self.endpoint))?
+            .into_json()
+            .context("Embedding server sent an unexpected response")?;
+        if response.embeddings.len() != count {
+            anyhow::bail!("Embedding server returned {} vectors for {} texts", response.embeddings.len(), count);
+        }
+        Ok(response.embeddings)
+    }
+}
+
+impl Embedder for HttpEmbedder {
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        let embeddings = self.request(texts)?;
+        let Some(first) = embeddings.first() else {
+            return Ok(embeddings);
+        };
+        let dim = *self.dim.get_or_init(|| first.len());
+        if let Some(bad) = embeddings.iter().find(|v| v.len() != dim) {
+            anyhow::bail!("Embedding server returned a {}-dimensional vector, expected {}", bad.len(), dim);
+        }
+        Ok(embeddings)
+    }
+
+    fn dim(&self) -> usize {
+        self.dim.get().copied().unwrap_or(0)
+    }
+}
+
+/// Hashes each word into one of `dim` buckets. Deterministic and instant, with no model to
+/// download, so texts sharing words land close together. Meant for tests, not for real search.
+pub struct HashEmbedder {
+    dim: usize,
+}
+
+impl HashEmbedder {
+    pub fn new(dim: usize) -> Self {
+        Self { dim: dim.max(1) }
+    }
+
+    fn embed_one(&self, text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0f32; self.dim];
+        for word in text.split(|c: char| !c.is_alphanumeric() && c != '_').filter(|w| !w.is_empty()) {
+            let hash = blake3::hash(word.to_lowercase().as_bytes());
+            let bytes = hash.as_bytes();
+            let bucket = u64::from_le_bytes(bytes[..8].try_into().unwrap()) % self.dim as u64;
+            vector[bucket as usize] += if bytes[8] & 1 == 0 { 1.0 } else { -1.0 };
+        }
+        let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|x| *x /= norm);
+        }
+        vector
+    }
+}
+
+impl Embedder for HashEmbedder {
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        Ok(texts.iter().map(|text| self.embed_one(text)).collect())
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+}
@@ -0,0 +1,247 @@
+//! Searching every registered project at once.
+//!
+//! Each distinct embedding backend is loaded once and the query embedded once with it; projects
+//! are then opened read-only, searched concurrently and their hits merged into a single ranking.
+//! Projects that are missing, fail or miss the deadline are reported rather than silently
+//! dropped.
+
+use super::embedder::{self, Embedder};
+use super::{active_index, Ranking, SearchEngine, SearchFilters, SearchHit, SearchMode};
+use crate::config::Config;
+use crate::db::Database;
+use crate::hub::ProjectInfo;
+use anyhow::Result;
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+/// How long a global search waits for the slowest project.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What to look for in every project.
+#[derive(Clone)]
+pub enum GlobalQuery {
+    /// Like `stasher ask`.
+    Search { query: String, mode: SearchMode },
+    /// Like `stasher similar`.
+    Similar { code: String },
+}
+
+pub struct ProjectHit {
+    pub project: String,
+    pub hit: SearchHit,
+}
+
+pub struct GlobalResults {
+    /// Each project's best hit, then each one's second best, and so on.
+    pub hits: Vec<ProjectHit>,
+    /// Projects that weren't searched, with the reason.
+    pub failures: Vec<(String, String)>,
+}
+
+/// A loaded backend and the query embedded with it, shared by every project using that model.
+#[derive(Clone)]
+struct SharedModel {
+    embedder: Option<Arc<dyn Embedder>>,
+    query_vec: Option<Vec<f32>>,
+}
+
+/// Loading a model and embedding the query, awaited by every project using that model.
+/// Errors are strings so the result can be cloned to each of them.
+type ModelLoad = Shared<BoxFuture<'static, Result<SharedModel, String>>>;
+
+pub async fn search_projects(
+    projects: Vec<ProjectInfo>,
+    query: GlobalQuery,
+    limit: usize,
+    filters: &SearchFilters,
+    ranking: &Ranking,
+    timeout: Duration,
+) -> Result<GlobalResults> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut failures = Vec::new();
+    let mut models: HashMap<String, ModelLoad> = HashMap::new();
+    let mut tasks = JoinSet::new();
+    let mut pending: HashMap<tokio::task::Id, String> = HashMap::new();
+
+    for project in projects {
+        let path = PathBuf::from(&project.path);
+        if !path.exists() {
+            failures.push((project.name, format!("{} no longer exists", project.path)));
+            continue;
+        }
+        let config = match Config::load(&path) {
+            Ok(config) => config,
+            Err(e) => {
+                failures.push((project.name, format!("{:#}", e)));
+                continue;
+            }
+        };
+
+        // Models load in the background under the same deadline as the searches
+        let model_id = embedder::model_id(&config);
+        let model = models
+            .entry(model_id.clone())
+            .or_insert_with(|| load_shared(config, query.clone()).boxed().shared())
+            .clone();
+
+        let query = query.clone();
+        let filters = filters.clone();
+        let ranking = *ranking;
+        let name = project.name.clone();
+        let handle = tasks.spawn(async move {
+            let hits = match model.await {
+                Ok(shared) => search_project(path, model_id, shared, query, limit, &filters, &ranking).await,
+                Err(e) => Err(anyhow::anyhow!(e)),
+            };
+            (name, hits)
+        });
+        pending.insert(handle.id(), project.name);
+    }
+
+    let mut per_project: Vec<(String, Vec<SearchHit>)> = Vec::new();
+    loop {
+        match tokio::time::timeout_at(deadline, tasks.join_next_with_id()).await {
+            Ok(Some(Ok((id, (name, result))))) => {
+                pending.remove(&id);
+                match result {
+                    Ok(project_hits) => per_project.push((name, project_hits)),
+                    Err(e) => failures.push((name, format!("{:#}", e))),
+                }
+            }
+            Ok(Some(Err(e))) => {
+                if let Some(name) = pending.remove(&e.id()) {
+                    failures.push((name, format!("search task failed: {}", e)));
+                }
+            }
+            Ok(None) => break,
+            Err(_) => {
+                tasks.abort_all();
+                failures.extend(pending.drain().map(|(_, name)| (name, format!("timed out after {}s", timeout.as_secs()))));
+                break;
+            }
+        }
+    }
+
+    Ok(GlobalResults { hits: interleave(per_project, limit), failures })
+}
+
+/// Merges per-project rankings by rank: every project's best hit, then every second best, and
+/// so on, with projects in name order within a rank. Scores come from separate fusions and
+/// aren't comparable across projects.
+fn interleave(mut per_project: Vec<(String, Vec<SearchHit>)>, limit: usize) -> Vec<ProjectHit> {
+    per_project.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut rankings: Vec<(String, std::vec::IntoIter<SearchHit>)> = per_project
+        .into_iter()
+        .map(|(project, hits)| (project, hits.into_iter()))
+        .collect();
+
+    let mut hits = Vec::new();
+    while hits.len() < limit {
+        let before = hits.len();
+        for (project, ranking) in &mut rankings {
+            if let Some(hit) = ranking.next() {
+                hits.push(ProjectHit { project: project.clone(), hit });
+            }
+        }
+        if hits.len() == before {
+            break;
+        }
+    }
+    hits.truncate(limit);
+    hits
+}
+
+/// Loads the backend for `config` and embeds the query with it, off the async runtime.
+async fn load_shared(config: Config, query: GlobalQuery) -> Result<SharedModel, String> {
+    match tokio::task::spawn_blocking(move || load_blocking(&config, &query)).await {
+        Ok(loaded) => loaded.map_err(|e| format!("{:#}", e)),
+        Err(e) => Err(format!("loading the embedding model failed: {}", e)),
+    }
+}
+
+/// A backend that can't load leaves its projects on keyword search, as it would for a single
+/// project. A query that fails to embed fails the projects using that backend.
+fn load_blocking(config: &Config, query: &GlobalQuery) -> Result<SharedModel> {
+    let embedder: Option<Arc<dyn Embedder>> = match embedder::load(config) {
+        Ok(embedder) => Some(Arc::from(embedder)),
+        Err(e) => {
+            eprintln!("⚠️ Semantic search unavailable for {}, using keyword search only: {:#}", embedder::model_id(config), e);
+            None
+        }
+    };
+    let text = match query {
+        GlobalQuery::Search { query, mode: SearchMode::Hybrid } => Some(query),
+        GlobalQuery::Search { .. } => None,
+        GlobalQuery::Similar { code } => Some(code),
+    };
+    let query_vec = match (&embedder, text) {
+        (Some(embedder), Some(text)) => Some(embedder.embed(vec![text.clone()])?.remove(0)),
+        _ => None,
+    };
+    Ok(SharedModel { embedder, query_vec })
+}
+
+async fn search_project(
+    path: PathBuf,
+    model_id: String,
+    shared: SharedModel,
+    query: GlobalQuery,
+    limit: usize,
+    filters: &SearchFilters,
+    ranking: &Ranking,
+) -> Result<Vec<SearchHit>> {
+    let db = Database::open_read_only(&path).await?;
+    let index = active_index(&db).await?;
+    let engine = SearchEngine::with_embedder(&db, model_id, shared.embedder, index)?;
+    match query {
+        GlobalQuery::Search { query, mode } => engine.search_embedded(query, shared.query_vec, limit, mode, filters, ranking).await,
+        GlobalQuery::Similar { code } => engine.similar_embedded(&code, shared.query_vec, limit, filters, ranking).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::interleave;
+    use crate::search::{Occurrence, SearchHit, SearchRecord};
+
+    fn hit(score: f64) -> SearchHit {
+        SearchHit {
+            record: SearchRecord {
+                key: String::new(),
+                kind: "content".to_string(),
+                start_line: 1,
+                end_line: 1,
+                content: String::new(),
+                vector: Vec::new(),
+            },
+            occurrences: vec![Occurrence {
+                snapshot_id: String::new(),
+                file_path: String::new(),
+                timestamp: 0,
+                session_id: String::new(),
+                branch: None,
+            }],
+            score,
+            distance: None,
+            folded: 0,
+            shown: 0,
+        }
+    }
+
+    #[test]
+    fn merges_projects_by_rank_not_score() {
+        let merged = interleave(
+            vec![
+                ("web".to_string(), vec![hit(0.9), hit(0.8), hit(0.7)]),
+                ("api".to_string(), vec![hit(0.02), hit(0.01)]),
+            ],
+            4,
+        );
+        let order: Vec<(&str, f64)> = merged.iter().map(|h| (h.project.as_str(), h.hit.score)).collect();
+        assert_eq!(order, [("api", 0.02), ("web", 0.9), ("api", 0.01), ("web", 0.8)]);
+    }
+}
@@ -1,124 +1,1237 @@
 use anyhow::{Context, Result};
-use fastembed::{TextEmbedding, InitOptions, EmbeddingModel};
 use lancedb::query::{ExecutableQuery, QueryBase};
 use lancedb::Connection;
 use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use arrow_array::{RecordBatch, StringArray, RecordBatchIterator};
+use arrow_array::{Array, FixedSizeListArray, Float32Array, RecordBatch, StringArray, RecordBatchIterator, UInt32Array};
 use arrow_schema::{DataType, Field, Schema};
+use crate::config::{Config, DEFAULT_EMBEDDING_MODEL};
+use crate::db::{fts5_query, Database};
 
-#[derive(Debug, Serialize, Deserialize)]
+pub mod ann;
+pub mod chunker;
+pub mod embedder;
+pub mod global;
+pub mod models;
+
+/// How `SearchEngine::search` matches the query.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SearchMode {
+    /// Keyword and vector rankings fused, with verbatim matches boosted.
+    Hybrid,
+    /// Chunks containing the query verbatim.
+    Exact,
+    /// Chunks matching the query as a regular expression.
+    Regex,
+}
+
+/// One embedded chunk. Content chunks are keyed by the BLAKE3 hash of the file they came from,
+/// so identical content is embedded once however many snapshots share it. Added and removed
+/// chunks depend on the previous version, so they're keyed by the snapshot that made the change.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct SearchRecord {
-    pub snapshot_id: String,
-    pub file_path: String,
+    pub key: String,
+    pub kind: String,
+    /// 1-based, inclusive.
+    pub start_line: u32,
+    /// 1-based, inclusive.
+    pub end_line: u32,
     pub content: String,
+    #[sqlx(skip)]
     pub vector: Vec<f32>,
 }
 
+impl SearchRecord {
+    fn is_content(&self) -> bool {
+        self.kind == chunker::ChunkKind::Content.as_str()
+    }
+}
+
+/// A snapshot in which a search hit's content appeared.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Occurrence {
+    pub snapshot_id: String,
+    pub file_path: String,
+    pub timestamp: i64,
+    pub session_id: String,
+    pub branch: Option<String>,
+}
+
+/// A matching chunk and every snapshot it appears in, standing for its whole file lineage.
+pub struct SearchHit {
+    pub record: SearchRecord,
+    /// Newest first. Never empty.
+    pub occurrences: Vec<Occurrence>,
+    /// Relevance after time decay. Only comparable within one search.
+    pub score: f64,
+    /// Vector distance from the query, when the chunk was a semantic match.
+    pub distance: Option<f32>,
+    /// Other matching chunks from the same file lineage that this hit outranked.
+    pub folded: usize,
+    shown: usize,
+}
+
+impl SearchHit {
+    /// The occurrence the ranking preferred: the latest or the first appearance.
+    pub fn shown(&self) -> &Occurrence {
+        &self.occurrences[self.shown]
+    }
+}
+
+/// Which appearance of a chunk represents it and drives time decay.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Prefer {
+    #[default]
+    Latest,
+    First,
+}
+
+/// How matches are ordered once found.
+#[derive(Clone, Copy, Default)]
+pub struct Ranking {
+    /// Halves a hit's score for every this many days since its shown appearance. `None` ranks
+    /// on relevance alone.
+    pub half_life_days: Option<f64>,
+    pub prefer: Prefer,
+}
+
+/// Identifies a chunk across the keyword and vector rankings: key, kind and first line.
+type ChunkKey = (String, String, u32);
+
+/// A match before grouping: its relevance, vector distance, chunk and occurrences.
+type Candidate = (f64, Option<f32>, SearchRecord, Vec<Occurrence>);
+
+/// An occurrence with the content it saved, for mapping content chunks back onto snapshots.
+#[derive(sqlx::FromRow)]
+struct OccurrenceRow {
+    #[sqlx(flatten)]
+    occurrence: Occurrence,
+    content_hash: String,
+}
+
+/// The snapshot whose content is being indexed.
+pub struct SnapshotMeta {
+    pub snapshot_id: String,
+    pub file_path: String,
+    pub content_hash: String,
+}
+
+/// Restricts a search to snapshots with these attributes. Chunks are shared between snapshots,
+/// so the filters select snapshots in SQLite; the keyword query joins against them and the
+/// vector query is limited to their chunk keys.
+#[derive(Default, Clone)]
+pub struct SearchFilters {
+    pub path: Option<String>,
+    /// Milliseconds, inclusive.
+    pub since: Option<i64>,
+    /// Milliseconds, inclusive.
+    pub until: Option<i64>,
+    pub session: Option<String>,
+    pub branch: Option<String>,
+    pub lang: Option<String>,
+}
+
+impl SearchFilters {
+    pub fn is_empty(&self) -> bool {
+        self.path.is_none()
+            && self.since.is_none()
+            && self.until.is_none()
+            && self.session.is_none()
+            && self.branch.is_none()
+            && self.lang.is_none()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(glob) = &self.path {
+            globset::Glob::new(glob).context(format!("Invalid path glob: {}", glob))?;
+        }
+        if let Some(lang) = &self.lang
+            && chunker::extensions_for(lang).is_none() {
+            let known: Vec<&str> = chunker::LANGUAGES.iter().map(|(name, _)| *name).collect();
+            anyhow::bail!("Unknown language '{}'. Known languages: {}", lang, known.join(", "));
+        }
+        Ok(())
+    }
+
+    /// Appends the filters to a query over `snapshots s` joined with `sessions se`. The path
+    /// glob is approximated with `LIKE` here and matched exactly by `keeps`.
+    pub fn push_sql(&self, query: &mut sqlx::QueryBuilder<'_, sqlx::Sqlite>) {
+        if let Some(glob) = &self.path {
+            query.push(" AND s.file_path LIKE ").push_bind(glob_to_like(glob)).push(" ESCAPE '\\'");
+        }
+        if let Some(since) = self.since {
+            query.push(" AND s.timestamp >= ").push_bind(since);
+        }
+        if let Some(until) = self.until {
+            query.push(" AND s.timestamp <= ").push_bind(until);
+        }
+        if let Some(session) = &self.session {
+            query.push(" AND s.session_id = ").push_bind(session.clone());
+        }
+        if let Some(branch) = &self.branch {
+            query.push(" AND se.branch = ").push_bind(branch.clone());
+        }
+        if let Some(extensions) = self.lang.as_deref().and_then(chunker::extensions_for) {
+            query.push(" AND (");
+            for (i, ext) in extensions.iter().enumerate() {
+                if i > 0 {
+                    query.push(" OR ");
+                }
+                query.push("s.file_path LIKE ").push_bind(format!("%.{}", ext));
+            }
+            query.push(")");
+        }
+    }
+
+    pub fn keeps(&self, file_path: &str) -> bool {
+        match &self.path {
+            Some(glob) => globset::Glob::new(glob)
+                .map(|g| g.compile_matcher().is_match(file_path))
+                .unwrap_or(false),
+            None => true,
+        }
+    }
+}
+
+/// Limits a query over `chunks_fts` to chunks that appear in a snapshot passing `filters`.
+fn push_chunk_filters(query: &mut sqlx::QueryBuilder<'_, sqlx::Sqlite>, filters: &SearchFilters) {
+    if filters.is_empty() {
+        return;
+    }
+    query.push(
+        " AND EXISTS (SELECT 1 FROM snapshots s
+         LEFT JOIN sessions se ON se.id = s.session_id
+         WHERE ((chunks_fts.kind = 'content' AND s.content_hash = chunks_fts.key)
+             OR (chunks_fts.kind != 'content' AND s.id = chunks_fts.key))"
+    );
+    filters.push_sql(query);
+    query.push(")");
+}
+
+/// A `LIKE` pattern, escaped with `\`, matching at least everything the glob does. Brace
+/// alternatives, character classes and escapes have no `LIKE` equivalent, so the pattern ends
+/// at the first one and matches anything from there on.
+fn glob_to_like(glob: &str) -> String {
+    let mut like = String::new();
+    let mut wildcard = false;
+    for c in glob.chars() {
+        match c {
+            // A run of wildcards matches what one does
+            '*' if wildcard => {}
+            '*' => like.push('%'),
+            '{' | '[' | '\\' => {
+                if !wildcard {
+                    like.push('%');
+                }
+                return like;
+            }
+            '?' => like.push('_'),
+            '%' | '_' => {
+                like.push('\\');
+                like.push(c);
+            }
+            c => like.push(c),
+        }
+        wildcard = c == '*';
+    }
+    like
+}
+
+/// The live generation of vector tables and the model that filled them. `stasher reindex`
+/// builds the next generation alongside and switches the pointer in `settings` once it's done.
+pub struct IndexInfo {
+    pub generation: String,
+    /// `None` until the first vector is written.
+    pub model: Option<String>,
+}
+
+impl IndexInfo {
+    pub fn chunks_table(&self) -> String {
+        format!("chunks_{}", self.generation)
+    }
+
+    pub fn notes_table(&self) -> String {
+        format!("notes_{}", self.generation)
+    }
+}
+
+/// Reads the live index generation and the model recorded in its table metadata.
+pub async fn active_index(db: &Database) -> Result<IndexInfo> {
+    let generation: Option<String> = sqlx::query_scalar("SELECT value FROM settings WHERE key = ?")
+        .bind(GENERATION_SETTING)
+        .fetch_optional(&db.sqlite)
+        .await?;
+    let mut info = IndexInfo {
+        generation: generation.unwrap_or_else(|| LEGACY_GENERATION.to_string()),
+        model: None,
+    };
+
+    let table_names: Vec<String> = db.lancedb.table_names().execute().await?;
+    if table_names.contains(&info.chunks_table()) {
+        let table = db.lancedb.open_table(info.chunks_table()).execute().await?;
+        let schema = table.schema().await?;
+        // Tables from before models were configurable carry no metadata and were always Nomic
+        info.model = Some(
+            schema.metadata().get(MODEL_METADATA_KEY)
+                .cloned()
+                .unwrap_or_else(|| DEFAULT_EMBEDDING_MODEL.to_string()),
+        );
+    }
+    Ok(info)
+}
+
+/// The `snapshots_v*` vector tables that predate content-addressed chunks. Their vectors can't
+/// be mapped onto `chunks_*`, so they're left in place until `stasher reindex` replaces them.
+pub async fn legacy_tables(lancedb: &Connection) -> Result<Vec<String>> {
+    let table_names: Vec<String> = lancedb.table_names().execute().await?;
+    Ok(table_names.into_iter().filter(|name| name.starts_with("snapshots_v")).collect())
+}
+
+/// Records that some history is indexed for keywords only, until `stasher reindex` runs.
+pub async fn mark_vectors_incomplete(sqlite: &SqlitePool) -> Result<()> {
+    sqlx::query("INSERT OR IGNORE INTO settings (key, value) VALUES (?, '1')")
+        .bind(INCOMPLETE_SETTING)
+        .execute(sqlite)
+        .await?;
+    Ok(())
+}
+
+/// Indexes records for keyword search alone, leaving `stasher reindex` to fill in the vectors.
+pub async fn write_keywords_only(sqlite: &SqlitePool, records: &[SearchRecord]) -> Result<()> {
+    if records.is_empty() {
+        return Ok(());
+    }
+    mark_vectors_incomplete(sqlite).await?;
+    write_lexical(sqlite, records).await
+}
+
+/// Whether some history was indexed for keywords only and needs `stasher reindex` for vectors.
+pub async fn vectors_incomplete(sqlite: &SqlitePool) -> Result<bool> {
+    let flag: Option<String> = sqlx::query_scalar("SELECT value FROM settings WHERE key = ?")
+        .bind(INCOMPLETE_SETTING)
+        .fetch_optional(sqlite)
+        .await?;
+    Ok(flag.is_some())
+}
+
+/// Makes `generation` the live index, which has vectors for everything. Run inside the
+/// transaction that rebuilds the lexical index.
+pub async fn set_active_generation(tx: &mut sqlx::SqliteConnection, generation: &str) -> Result<()> {
+    sqlx::query("INSERT INTO settings (key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value")
+        .bind(GENERATION_SETTING)
+        .bind(generation)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM settings WHERE key = ?")
+        .bind(INCOMPLETE_SETTING)
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
 pub struct SearchEngine {
-    model: tokio::sync::Mutex<TextEmbedding>,
+    /// `None` when no model could be loaded: snapshots are still indexed for keyword search,
+    /// and searches run on keywords alone.
+    embedder: Option<Arc<dyn embedder::Embedder>>,
+    model_code: String,
+    index: IndexInfo,
     lancedb: Connection,
+    sqlite: SqlitePool,
 }
 
 impl SearchEngine {
-    pub async fn new(lancedb: Connection) -> Result<Self> {
-        let mut options = InitOptions::default();
-        options.model_name = EmbeddingModel::NomicEmbedTextV15;
-        options.show_download_progress = true;
-        
-        let model = TextEmbedding::try_new(options)?;
+    /// Opens the live index with the project's configured embedding backend.
+    pub async fn new(db: &Database, config: &Config) -> Result<Self> {
+        let index = active_index(db).await?;
+        Self::with_index(db, config, index).await
+    }
 
+    /// Opens (or prepares) a specific index generation with the configured embedding backend.
+    pub async fn with_index(db: &Database, config: &Config, index: IndexInfo) -> Result<Self> {
+        // Loading a model reads (or downloads) hundreds of megabytes
+        let load_config = config.clone();
+        let loaded = tokio::task::spawn_blocking(move || embedder::load(&load_config)).await?;
+        let embedder = match loaded {
+            Ok(embedder) => Some(Arc::from(embedder)),
+            Err(e) => {
+                eprintln!("⚠️ Semantic search unavailable, using keyword search only: {:#}", e);
+                None
+            }
+        };
+        Self::with_embedder(db, embedder::model_id(config), embedder, index)
+    }
+
+    /// Uses `embedder` directly, recording vectors under `model_code`. Lets several projects
+    /// share one loaded model, or tests bring a `HashEmbedder`.
+    pub fn with_embedder(db: &Database, model_code: String, embedder: Option<Arc<dyn embedder::Embedder>>, index: IndexInfo) -> Result<Self> {
         Ok(Self {
-            model: tokio::sync::Mutex::new(model),
-            lancedb,
+            embedder,
+            model_code,
+            index,
+            lancedb: db.lancedb.clone(),
+            sqlite: db.sqlite.clone(),
         })
     }
 
-    pub async fn index_snapshot(&self, snapshot_id: String, file_path: String, content: String) -> Result<()> {
-        let embeddings = {
-            let mut model = self.model.lock().await;
-            model.embed(vec![content.clone()], None)?
+    pub fn index(&self) -> &IndexInfo {
+        &self.index
+    }
+
+    pub fn has_model(&self) -> bool {
+        self.embedder.is_some()
+    }
+
+    fn dim(&self) -> Result<i32> {
+        let embedder = self.embedder.as_ref().context("No embedding model loaded")?;
+        match embedder.dim() {
+            0 => anyhow::bail!("The embedding dimension isn't known until something is embedded"),
+            dim => Ok(dim as i32),
+        }
+    }
+
+    /// Embeds `texts` off the async runtime, or returns `None` when running without a model.
+    async fn embed(&self, texts: Vec<String>) -> Result<Option<Vec<Vec<f32>>>> {
+        let Some(embedder) = self.embedder.clone() else {
+            return Ok(None);
+        };
+        let vectors = tokio::task::spawn_blocking(move || embedder.embed(texts)).await??;
+        Ok(Some(vectors))
+    }
+
+    /// Vectors from different models live in unrelated spaces, so comparing or mixing them
+    /// would return nonsense rather than fail.
+    fn ensure_compatible(&self) -> Result<()> {
+        match &self.index.model {
+            Some(built_with) if built_with != &self.model_code => anyhow::bail!(
+                "The search index was built with {} but the configured model is {}. Run `stasher reindex` to rebuild it.",
+                built_with,
+                self.model_code
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Splits the snapshot into chunks and embeds them in one batch. Each chunk is embedded with
+    /// its file path in front so results stay anchored to where the code lives. Content that was
+    /// indexed before (a revert, copy or restore) is skipped. When `patch` is given, the hunks it
+    /// added and removed are indexed too, so deleted code stays findable.
+    pub async fn index_snapshot(&self, meta: SnapshotMeta, content: String, patch: Option<&str>) -> Result<()> {
+        self.ensure_compatible()?;
+        let content = if is_indexed(&self.sqlite, &meta.content_hash).await? {
+            None
+        } else {
+            Some(content.as_str())
+        };
+
+        let records = match self.embed_snapshot(&meta, content, patch).await {
+            Ok(records) => records,
+            Err(e) => {
+                // Keep the snapshot findable by keyword; `stasher reindex` fills in the vectors
+                let records: Vec<SearchRecord> = snapshot_chunks(&meta, content, patch).into_iter().map(|(record, _)| record).collect();
+                write_keywords_only(&self.sqlite, &records).await?;
+                return Err(e);
+            }
         };
-        let vector = embeddings[0].clone();
+        if !self.has_model() || !self.is_live().await? {
+            return write_keywords_only(&self.sqlite, &records).await;
+        }
+        // Vectors first: content only counts as indexed once both stores have it
+        self.write_vectors(records.clone()).await?;
+        write_lexical(&self.sqlite, &records).await
+    }
+
+    /// Chunks and embeds a snapshot's content and/or the hunks of its patch, without storing them.
+    /// Without a model the records carry no vectors.
+    pub async fn embed_snapshot(&self, meta: &SnapshotMeta, content: Option<&str>, patch: Option<&str>) -> Result<Vec<SearchRecord>> {
+        let chunks = snapshot_chunks(meta, content, patch);
+        if chunks.is_empty() {
+            return Ok(Vec::new());
+        }
 
-        let schema = Arc::new(Schema::new(vec![
-            Field::new("snapshot_id", DataType::Utf8, false),
-            Field::new("file_path", DataType::Utf8, false),
-            Field::new("content", DataType::Utf8, false),
-            Field::new("vector", DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Float32, true)), 768), false),
-        ]));
+        let texts: Vec<String> = chunks.iter().map(|(_, text)| text.clone()).collect();
+        let count = texts.len();
+        let embeddings = self.embed(texts).await?.unwrap_or_else(|| vec![Vec::new(); count]);
 
+        let records: Vec<SearchRecord> = chunks
+            .into_iter()
+            .zip(embeddings)
+            .map(|((mut record, _), vector)| {
+                record.vector = vector;
+                record
+            })
+            .collect();
+        Ok(records)
+    }
+
+    /// Appends embedded records to this generation's chunk table.
+    pub async fn write_vectors(&self, records: Vec<SearchRecord>) -> Result<()> {
+        write_records(&self.lancedb, &self.index.chunks_table(), &self.model_code, self.dim()?, records).await
+    }
+
+    /// Whether this engine's generation is still the live one. A long-running process like the
+    /// daemon can outlive a `stasher reindex` that switched generations, so writers check first.
+    async fn is_live(&self) -> Result<bool> {
+        let generation: Option<String> = sqlx::query_scalar("SELECT value FROM settings WHERE key = ?")
+            .bind(GENERATION_SETTING)
+            .fetch_optional(&self.sqlite)
+            .await?;
+        Ok(generation.as_deref().unwrap_or(LEGACY_GENERATION) == self.index.generation)
+    }
+
+    pub async fn index_note(&self, note_id: &str, content: &str) -> Result<()> {
+        if self.has_model() && !self.is_live().await? {
+            // Its keywords are in notes_fts already; the vector waits for `stasher reindex`
+            return mark_vectors_incomplete(&self.sqlite).await;
+        }
+        self.write_note_vector(note_id, content).await
+    }
+
+    /// Embeds a note into this generation's notes table.
+    pub async fn write_note_vector(&self, note_id: &str, content: &str) -> Result<()> {
+        self.ensure_compatible()?;
+        let Some(mut vectors) = self.embed(vec![content.to_string()]).await? else {
+            return Ok(());
+        };
+        let vector = vectors.remove(0);
+        let dim = self.dim()?;
+
+        let schema = Arc::new(Schema::new_with_metadata(
+            vec![
+                Field::new("note_id", DataType::Utf8, false),
+                Field::new("vector", DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Float32, true)), dim), false),
+            ],
+            model_metadata(&self.model_code, dim),
+        ));
         let batch = RecordBatch::try_new(
             schema.clone(),
             vec![
-                Arc::new(StringArray::from(vec![snapshot_id])),
-                Arc::new(StringArray::from(vec![file_path])),
-                Arc::new(StringArray::from(vec![content])),
+                Arc::new(StringArray::from(vec![note_id.to_string()])),
                 Arc::new(arrow_array::FixedSizeListArray::from_iter_primitive::<arrow_array::types::Float32Type, _, _>(
                     vec![Some(vector.into_iter().map(Some).collect::<Vec<_>>())],
-                    768
+                    dim
                 )),
             ],
         )?;
 
         let table_names: Vec<String> = self.lancedb.table_names().execute().await?;
-        let batches = RecordBatchIterator::new(vec![Ok(batch)], schema.clone());
-
-        if table_names.contains(&"snapshots_v1".to_string()) {
-            let table = self.lancedb.open_table("snapshots_v1").execute().await?;
+        let batches = RecordBatchIterator::new(vec![Ok(batch)], schema);
+        let notes_table = self.index.notes_table();
+        if table_names.contains(&notes_table) {
+            let table = self.lancedb.open_table(&notes_table).execute().await?;
             table.add(batches).execute().await?;
         } else {
-            self.lancedb
-                .create_table("snapshots_v1", batches)
-                .execute()
-                .await?;
+            self.lancedb.create_table(&notes_table, batches).execute().await?;
         }
+        Ok(())
+    }
 
+    pub async fn remove_note(&self, note_id: &str) -> Result<()> {
+        let table_names: Vec<String> = self.lancedb.table_names().execute().await?;
+        if table_names.contains(&self.index.notes_table()) {
+            let table = self.lancedb.open_table(self.index.notes_table()).execute().await?;
+            table.delete(&format!("note_id = '{}'", note_id.replace('\'', "''"))).await?;
+        }
         Ok(())
     }
 
-    pub async fn search(&self, query: String, limit: usize) -> Result<Vec<SearchRecord>> {
+    /// Ids of the notes closest in meaning to `query`, best first.
+    pub async fn search_notes(&self, query: &str, limit: usize) -> Result<Vec<String>> {
         use futures_util::StreamExt;
-        let query_vec = {
-            let mut model = self.model.lock().await;
-            model.embed(vec![query], None)?[0].clone()
+
+        let table_names: Vec<String> = self.lancedb.table_names().execute().await?;
+        if !table_names.contains(&self.index.notes_table()) {
+            return Ok(Vec::new());
+        }
+        self.ensure_compatible()?;
+
+        let Some(mut vectors) = self.embed(vec![query.to_string()]).await? else {
+            return Ok(Vec::new());
         };
-        
-        let table = self.lancedb.open_table("snapshots_v1").execute().await?;
+        let query_vec = vectors.remove(0);
+        let table = self.lancedb.open_table(self.index.notes_table()).execute().await?;
         let mut results = table
             .vector_search(query_vec)?
             .limit(limit)
             .execute()
-                .await?;
-            
-        let mut records = Vec::new();
+            .await?;
+
+        let mut ids = Vec::new();
         while let Some(batch) = results.next().await {
             let batch = batch?;
-            let snapshot_ids = batch.column_by_name("snapshot_id")
-                .context("Missing snapshot_id column")?
-                .as_any().downcast_ref::<StringArray>()
-                .context("Failed to downcast snapshot_id")?;
-            
-            let file_paths = batch.column_by_name("file_path")
-                .context("Missing file_path column")?
-                .as_any().downcast_ref::<StringArray>()
-                .context("Failed to downcast file_path")?;
-            
-            let contents = batch.column_by_name("content")
-                .context("Missing content column")?
-                .as_any().downcast_ref::<StringArray>()
-                .context("Failed to downcast content")?;
-
+            let note_ids = string_column(&batch, "note_id")?;
             for i in 0..batch.num_rows() {
-                records.push(SearchRecord {
-                    snapshot_id: snapshot_ids.value(i).to_string(),
-                    file_path: file_paths.value(i).to_string(),
-                    content: contents.value(i).to_string(),
-                    vector: vec![],
+                ids.push(note_ids.value(i).to_string());
+            }
+        }
+        Ok(ids)
+    }
+
+    pub async fn search(&self, query: String, limit: usize, mode: SearchMode, filters: &SearchFilters, ranking: &Ranking) -> Result<Vec<SearchHit>> {
+        let query_vec = match mode {
+            SearchMode::Hybrid => match self.embed_query(&query).await {
+                Ok(query_vec) => query_vec,
+                Err(e) => {
+                    eprintln!("⚠️ Semantic search unavailable, using keyword search only: {:#}", e);
+                    None
+                }
+            },
+            _ => None,
+        };
+        self.search_embedded(query, query_vec, limit, mode, filters, ranking).await
+    }
+
+    /// Embeds a query for `search_embedded`, or returns `None` when running without a model.
+    pub async fn embed_query(&self, query: &str) -> Result<Option<Vec<f32>>> {
+        Ok(self.embed(vec![query.to_string()]).await?.map(|mut vectors| vectors.remove(0)))
+    }
+
+    /// `search` with the query already embedded, so one embedding can serve many projects.
+    pub async fn search_embedded(&self, query: String, query_vec: Option<Vec<f32>>, limit: usize, mode: SearchMode, filters: &SearchFilters, ranking: &Ranking) -> Result<Vec<SearchHit>> {
+        filters.validate()?;
+        if mode == SearchMode::Hybrid {
+            self.ensure_compatible()?;
+        }
+        let candidates = match mode {
+            SearchMode::Hybrid => self.hybrid_search(query, query_vec, limit, filters).await?,
+            SearchMode::Exact => self.scan_search(limit, filters, Some(&query), |_| true).await?,
+            SearchMode::Regex => {
+                let regex = regex::Regex::new(&query).context("Invalid regular expression")?;
+                self.scan_search(limit, filters, None, move |content| regex.is_match(content)).await?
+            }
+        };
+        self.rank(candidates, limit, ranking).await
+    }
+
+    /// Fuses the keyword (BM25) and vector rankings with reciprocal rank fusion. Chunks that
+    /// contain the query verbatim outrank everything else, since that's almost always an
+    /// identifier the user typed on purpose.
+    ///
+    /// Both queries apply the filters, but grouping folds several matches into one hit, so the
+    /// search deepens until the matches fill `limit` hits, both rankings run out or it reaches
+    /// `MAX_SEARCH_DEPTH`.
+    async fn hybrid_search(&self, query: String, query_vec: Option<Vec<f32>>, limit: usize, filters: &SearchFilters) -> Result<Vec<Candidate>> {
+        let keys = self.filtered_keys(filters).await?;
+        let mut depth = limit * 4;
+        loop {
+            let lexical = self.lexical_search(&query, depth, filters).await?;
+            let semantic = match &query_vec {
+                Some(query_vec) => self.nearest(query_vec.clone(), depth, keys.as_deref()).await?,
+                None => Vec::new(),
+            };
+            let exhausted = lexical.len() < depth && semantic.len() < depth;
+
+            let matches = self.fuse(&query, lexical, semantic, filters).await?;
+            if exhausted || depth >= MAX_SEARCH_DEPTH || self.fills(&matches, limit).await? {
+                return Ok(matches);
+            }
+            depth = (depth * 4).min(MAX_SEARCH_DEPTH);
+        }
+    }
+
+    /// Reciprocal rank fusion of the keyword and vector rankings, keeping the chunks that
+    /// appear in a snapshot passing `filters`.
+    async fn fuse(&self, query: &str, lexical: Vec<SearchRecord>, semantic: Vec<(SearchRecord, f32)>, filters: &SearchFilters) -> Result<Vec<Candidate>> {
+        let lexical: Vec<(SearchRecord, Option<f32>)> = lexical
+            .into_iter()
+            .map(|record| (record, None))
+            .collect();
+        let semantic = semantic
+            .into_iter()
+            .map(|(record, distance)| (record, Some(distance)))
+            .collect();
+
+        let mut scored: HashMap<ChunkKey, (f64, Option<f32>, SearchRecord)> = HashMap::new();
+        for ranking in [lexical, semantic] {
+            for (rank, (record, distance)) in ranking.into_iter().enumerate() {
+                let key = (record.key.clone(), record.kind.clone(), record.start_line);
+                let entry = scored.entry(key).or_insert_with(|| {
+                    let boost = if record.content.contains(query.trim()) { 1.0 } else { 0.0 };
+                    (boost, None, record)
                 });
+                entry.0 += 1.0 / (60.0 + rank as f64);
+                entry.1 = entry.1.or(distance);
+            }
+        }
+
+        let mut ranked: Vec<(f64, Option<f32>, SearchRecord)> = scored.into_values().collect();
+        ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let records: Vec<&SearchRecord> = ranked.iter().map(|(_, _, record)| record).collect();
+        let occurrences = self.occurrences(&records, filters).await?;
+        let matches = ranked
+            .into_iter()
+            .zip(occurrences)
+            .filter(|(_, occurrences)| !occurrences.is_empty())
+            .map(|((score, distance, record), occurrences)| (score, distance, record, occurrences))
+            .collect();
+        Ok(matches)
+    }
+
+    /// Whether `candidates` span at least `limit` file lineages, so grouping them still
+    /// leaves `limit` hits.
+    async fn fills(&self, candidates: &[Candidate], limit: usize) -> Result<bool> {
+        let mut cache = HashMap::new();
+        let mut lineages = HashSet::new();
+        for (_, _, _, occurrences) in candidates {
+            lineages.insert(self.lineage(&occurrences[0].file_path, &mut cache).await?);
+            if lineages.len() >= limit {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Applies time decay, keeps the best match per file lineage and orders what's left.
+    /// A lineage follows a file back through moves, so a renamed file still counts once.
+    async fn rank(&self, candidates: Vec<Candidate>, limit: usize, ranking: &Ranking) -> Result<Vec<SearchHit>> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut lineages: HashMap<String, String> = HashMap::new();
+        let mut groups: Vec<(String, SearchHit)> = Vec::new();
+
+        for (relevance, distance, record, occurrences) in candidates {
+            let shown = match ranking.prefer {
+                Prefer::Latest => 0,
+                Prefer::First => occurrences.len() - 1,
+            };
+            let mut score = relevance;
+            if let Some(half_life) = ranking.half_life_days {
+                let age_days = (now - occurrences[shown].timestamp).max(0) as f64 / 86_400_000.0;
+                score *= 0.5f64.powf(age_days / half_life);
+            }
+            let lineage = self.lineage(&occurrences[shown].file_path, &mut lineages).await?;
+            let mut hit = SearchHit { record, occurrences, score, distance, folded: 0, shown };
+
+            match groups.iter_mut().find(|(root, _)| *root == lineage) {
+                Some((_, best)) if hit.score > best.score => {
+                    hit.folded = best.folded + 1;
+                    *best = hit;
+                }
+                Some((_, best)) => best.folded += 1,
+                None => groups.push((lineage, hit)),
+            }
+        }
+
+        let mut hits: Vec<SearchHit> = groups.into_iter().map(|(_, hit)| hit).collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(limit);
+        Ok(hits)
+    }
+
+    /// The earliest path in a file's history: where its first version's content had appeared
+    /// before, repeated until no older path is found. Matches how `show` follows moves.
+    async fn lineage(&self, file_path: &str, cache: &mut HashMap<String, String>) -> Result<String> {
+        if let Some(root) = cache.get(file_path) {
+            return Ok(root.clone());
+        }
+        let mut path = file_path.to_string();
+        let mut seen = HashSet::new();
+        while seen.insert(path.clone()) {
+            let origin: Option<String> = sqlx::query_scalar(
+                "SELECT p.file_path FROM snapshots p
+                 JOIN (SELECT content_hash, timestamp FROM snapshots WHERE file_path = ? ORDER BY timestamp ASC LIMIT 1) f
+                   ON p.content_hash = f.content_hash AND p.timestamp <= f.timestamp
+                 WHERE p.file_path != ?
+                 ORDER BY p.timestamp ASC LIMIT 1"
+            )
+            .bind(&path)
+            .bind(&path)
+            .fetch_optional(&self.sqlite)
+            .await?;
+            match origin {
+                Some(origin) => path = origin,
+                None => break,
+            }
+        }
+        cache.insert(file_path.to_string(), path.clone());
+        Ok(path)
+    }
+
+    /// Best keyword matches among chunks that appear in a snapshot passing `filters`.
+    async fn lexical_search(&self, query: &str, limit: usize, filters: &SearchFilters) -> Result<Vec<SearchRecord>> {
+        let fts_query = fts5_query(query);
+        if fts_query.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut sql = sqlx::QueryBuilder::new(format!("SELECT {} FROM chunks_fts WHERE chunks_fts MATCH ", FTS_COLUMNS));
+        sql.push_bind(fts_query);
+        push_chunk_filters(&mut sql, filters);
+        sql.push(" ORDER BY bm25(chunks_fts) LIMIT ").push_bind(limit as i64);
+
+        let records = sql.build_query_as::<SearchRecord>().fetch_all(&self.sqlite).await?;
+        Ok(records)
+    }
+
+    /// Walks the chunks of snapshots passing `filters`, newest first, keeping those that
+    /// contain `contains` and pass `matches` until they fill `limit` hits. Used for exact and
+    /// regex searches, which the full-text index can't answer on its own. Every match is equally
+    /// relevant, so newer ones stay ahead unless decay says otherwise.
+    async fn scan_search(&self, limit: usize, filters: &SearchFilters, contains: Option<&str>, matches: impl Fn(&str) -> bool) -> Result<Vec<Candidate>> {
+        use futures_util::TryStreamExt;
+
+        let mut sql = sqlx::QueryBuilder::new(format!("SELECT {} FROM chunks_fts WHERE 1 = 1", FTS_COLUMNS));
+        if let Some(needle) = contains {
+            sql.push(" AND instr(content, ").push_bind(needle.to_string()).push(") > 0");
+        }
+        push_chunk_filters(&mut sql, filters);
+        sql.push(" ORDER BY rowid DESC");
+        let mut rows = sql.build_query_as::<SearchRecord>().fetch(&self.sqlite);
+
+        let mut found = Vec::new();
+        let mut cache = HashMap::new();
+        let mut lineages = HashSet::new();
+        let mut batch = Vec::new();
+        loop {
+            let record = rows.try_next().await?;
+            let done = record.is_none();
+            if let Some(record) = record.filter(|r| matches(&r.content)) {
+                batch.push(record);
+            }
+            if batch.len() < SCAN_BATCH && !done {
+                continue;
+            }
+
+            // Occurrences are looked up a batch at a time rather than per chunk
+            let records: Vec<&SearchRecord> = batch.iter().collect();
+            let occurrences = self.occurrences(&records, filters).await?;
+            for (record, occurrences) in batch.drain(..).zip(occurrences) {
+                if occurrences.is_empty() {
+                    continue;
+                }
+                lineages.insert(self.lineage(&occurrences[0].file_path, &mut cache).await?);
+                found.push((1.0, None, record, occurrences));
+                if lineages.len() >= limit {
+                    return Ok(found);
+                }
+            }
+            if done {
+                return Ok(found);
+            }
+        }
+    }
+
+    /// Chunks that look like `code`, for finding an earlier implementation or duplicated logic.
+    /// Copies of `code` itself are left out.
+    pub async fn similar(&self, code: &str, limit: usize, filters: &SearchFilters, ranking: &Ranking) -> Result<Vec<SearchHit>> {
+        let code_vec = self.embed_query(code).await?;
+        self.similar_embedded(code, code_vec, limit, filters, ranking).await
+    }
+
+    /// `similar` with the code already embedded.
+    pub async fn similar_embedded(&self, code: &str, code_vec: Option<Vec<f32>>, limit: usize, filters: &SearchFilters, ranking: &Ranking) -> Result<Vec<SearchHit>> {
+        filters.validate()?;
+        self.ensure_compatible()?;
+        let Some(code_vec) = code_vec else {
+            anyhow::bail!("Similarity search needs an embedding model, and none is loaded");
+        };
+
+        // Deepen until the matches fill `limit` hits, the index runs out or the depth is capped
+        let keys = self.filtered_keys(filters).await?;
+        let mut depth = limit * 4;
+        loop {
+            let nearest = self.nearest(code_vec.clone(), depth, keys.as_deref()).await?;
+            let exhausted = nearest.len() < depth;
+
+            let nearest: Vec<(SearchRecord, f32)> = nearest
+                .into_iter()
+                .filter(|(record, _)| record.content.trim() != code.trim())
+                .collect();
+            let records: Vec<&SearchRecord> = nearest.iter().map(|(record, _)| record).collect();
+            let occurrences = self.occurrences(&records, filters).await?;
+            let candidates: Vec<Candidate> = nearest
+                .into_iter()
+                .zip(occurrences)
+                .filter(|(_, occurrences)| !occurrences.is_empty())
+                // Closer is better; keep the score positive so decay scales it sensibly
+                .map(|((record, distance), occurrences)| (1.0 / (1.0 + distance as f64), Some(distance), record, occurrences))
+                .collect();
+            if exhausted || depth >= MAX_SEARCH_DEPTH || self.fills(&candidates, limit).await? {
+                return self.rank(candidates, limit, ranking).await;
+            }
+            depth = (depth * 4).min(MAX_SEARCH_DEPTH);
+        }
+    }
+
+    /// Nearest chunks with their distance from the query, among the chunks with one of `keys`
+    /// when given.
+    async fn nearest(&self, query_vec: Vec<f32>, limit: usize, keys: Option<&[String]>) -> Result<Vec<(SearchRecord, f32)>> {
+        use futures_util::StreamExt;
+
+        let table_names: Vec<String> = self.lancedb.table_names().execute().await?;
+        if !table_names.contains(&self.index.chunks_table()) {
+            return Ok(Vec::new());
+        }
+        let table = self.lancedb.open_table(self.index.chunks_table()).execute().await?;
+        let mut query = table.vector_search(query_vec)?.limit(limit);
+        if let Some(keys) = keys {
+            if keys.is_empty() {
+                return Ok(Vec::new());
             }
+            // Applied before the nearest neighbours are picked, so every result passes
+            let quoted: Vec<String> = keys.iter().map(|key| format!("'{}'", key.replace('\'', "''"))).collect();
+            query = query.only_if(format!("key IN ({})", quoted.join(", ")));
+        }
+        let mut results = query.execute().await?;
+
+        let mut records = Vec::new();
+        while let Some(batch) = results.next().await {
+            let batch = batch?;
+            let distances = batch.column_by_name("_distance")
+                .context("Missing _distance column")?
+                .as_any().downcast_ref::<Float32Array>()
+                .context("Failed to downcast _distance")?
+                .clone();
+            records.extend(batch_records(&batch, false)?.into_iter().zip(distances.values().iter().copied()));
         }
-            
         Ok(records)
     }
+
+    /// The chunk keys (content hashes and snapshot ids) of the snapshots passing `filters`, for
+    /// the vector query's predicate. `None` without filters, or when so many snapshots pass that
+    /// the predicate would cost more than filtering the hits afterwards.
+    async fn filtered_keys(&self, filters: &SearchFilters) -> Result<Option<Vec<String>>> {
+        if filters.is_empty() {
+            return Ok(None);
+        }
+        let mut sql = sqlx::QueryBuilder::new(
+            "SELECT s.id, s.content_hash, s.file_path FROM snapshots s
+             LEFT JOIN sessions se ON se.id = s.session_id
+             WHERE 1 = 1"
+        );
+        filters.push_sql(&mut sql);
+        let rows: Vec<(String, String, String)> = sql.build_query_as().fetch_all(&self.sqlite).await?;
+
+        let mut keys = HashSet::new();
+        for (id, content_hash, file_path) in rows {
+            if filters.keeps(&file_path) {
+                keys.insert(id);
+                keys.insert(content_hash);
+            }
+            if keys.len() > MAX_PREDICATE_KEYS {
+                return Ok(None);
+            }
+        }
+        Ok(Some(keys.into_iter().collect()))
+    }
+
+    /// The snapshots each record belongs to that pass `filters`, newest first, in the order of
+    /// `records`. One query covers the whole batch.
+    async fn occurrences(&self, records: &[&SearchRecord], filters: &SearchFilters) -> Result<Vec<Vec<Occurrence>>> {
+        if records.is_empty() {
+            return Ok(Vec::new());
+        }
+        let hashes: Vec<&str> = records.iter().filter(|r| r.is_content()).map(|r| r.key.as_str()).collect();
+        let ids: Vec<&str> = records.iter().filter(|r| !r.is_content()).map(|r| r.key.as_str()).collect();
+
+        let mut sql = sqlx::QueryBuilder::new(
+            "SELECT s.id AS snapshot_id, s.file_path, s.timestamp, s.session_id, se.branch, s.content_hash
+             FROM snapshots s
+             LEFT JOIN sessions se ON se.id = s.session_id
+             WHERE (s.content_hash IN (SELECT value FROM json_each("
+        );
+        sql.push_bind(serde_json::to_string(&hashes)?)
+            .push(")) OR s.id IN (SELECT value FROM json_each(")
+            .push_bind(serde_json::to_string(&ids)?)
+            .push(")))");
+        filters.push_sql(&mut sql);
+        sql.push(" ORDER BY s.timestamp DESC");
+        let rows = sql.build_query_as::<OccurrenceRow>().fetch_all(&self.sqlite).await?;
+
+        let mut by_hash: HashMap<String, Vec<Occurrence>> = HashMap::new();
+        let mut by_id: HashMap<String, Occurrence> = HashMap::new();
+        for row in rows.into_iter().filter(|row| filters.keeps(&row.occurrence.file_path)) {
+            by_hash.entry(row.content_hash).or_default().push(row.occurrence.clone());
+            by_id.insert(row.occurrence.snapshot_id.clone(), row.occurrence);
+        }
+        Ok(records
+            .iter()
+            .map(|record| if record.is_content() {
+                by_hash.get(&record.key).cloned().unwrap_or_default()
+            } else {
+                by_id.get(&record.key).cloned().into_iter().collect()
+            })
+            .collect())
+    }
+}
+
+/// Generation of the tables written before `stasher reindex` existed.
+const LEGACY_GENERATION: &str = "v1";
+const GENERATION_SETTING: &str = "vector_generation";
+/// Set when snapshots were indexed without a model, cleared by `stasher reindex`.
+const INCOMPLETE_SETTING: &str = "vectors_incomplete";
+const MODEL_METADATA_KEY: &str = "stasher.embedding_model";
+const DIM_METADATA_KEY: &str = "stasher.embedding_dim";
+const FTS_COLUMNS: &str = "key, kind, start_line, end_line, content";
+/// Deepest a search goes looking for enough hits to fill its limit after grouping.
+const MAX_SEARCH_DEPTH: usize = 2_000;
+/// Chunks an exact or regex scan collects before looking up where they appeared.
+const SCAN_BATCH: usize = 200;
+/// Most chunk keys listed in a vector query's filter predicate. Filters matching more snapshots
+/// than this are broad enough that filtering the nearest hits afterwards finds enough of them.
+const MAX_PREDICATE_KEYS: usize = 4_000;
+
+fn model_metadata(model_code: &str, dim: i32) -> HashMap<String, String> {
+    HashMap::from([
+        (MODEL_METADATA_KEY.to_string(), model_code.to_string()),
+        (DIM_METADATA_KEY.to_string(), dim.to_string()),
+    ])
+}
+
+fn record_schema(model_code: &str, dim: i32) -> Arc<Schema> {
+    Arc::new(Schema::new_with_metadata(vec![
+        Field::new("key", DataType::Utf8, false),
+        Field::new("kind", DataType::Utf8, false),
+        Field::new("start_line", DataType::UInt32, false),
+        Field::new("end_line", DataType::UInt32, false),
+        Field::new("content", DataType::Utf8, false),
+        Field::new("vector", DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Float32, true)), dim), false),
+    ], model_metadata(model_code, dim)))
+}
+
+/// Splits a snapshot's content and/or patch into unembedded records, each paired with the text
+/// to embed for it: the chunk with its file path in front, so results stay anchored to where the
+/// code lives.
+pub fn snapshot_chunks(meta: &SnapshotMeta, content: Option<&str>, patch: Option<&str>) -> Vec<(SearchRecord, String)> {
+    let file_path = &meta.file_path;
+    let mut chunks: Vec<(chunker::ChunkKind, chunker::Chunk)> = Vec::new();
+    if let Some(content) = content {
+        chunks.extend(
+            chunker::chunk_file(file_path, content)
+                .into_iter()
+                .map(|chunk| (chunker::ChunkKind::Content, chunk)),
+        );
+    }
+    if let Some(patch) = patch {
+        chunks.extend(chunker::diff_chunks(patch));
+    }
+
+    chunks
+        .into_iter()
+        .map(|(kind, chunk)| {
+            let header = match kind {
+                chunker::ChunkKind::Content => "",
+                chunker::ChunkKind::Added => "Added to ",
+                chunker::ChunkKind::Removed => "Removed from ",
+            };
+            let text = format!("{}{}:{}-{}\n{}", header, file_path, chunk.start_line, chunk.end_line, chunk.text);
+            let record = SearchRecord {
+                key: match kind {
+                    chunker::ChunkKind::Content => meta.content_hash.clone(),
+                    _ => meta.snapshot_id.clone(),
+                },
+                kind: kind.as_str().to_string(),
+                start_line: chunk.start_line as u32,
+                end_line: chunk.end_line as u32,
+                content: chunk.text,
+                vector: Vec::new(),
+            };
+            (record, text)
+        })
+        .collect()
+}
+
+/// Whether chunks for this content hash are already in the index.
+pub async fn is_indexed(sqlite: &SqlitePool, content_hash: &str) -> Result<bool> {
+    let found: Option<String> = sqlx::query_scalar("SELECT content_hash FROM indexed_content WHERE content_hash = ?")
+        .bind(content_hash)
+        .fetch_optional(sqlite)
+        .await?;
+    Ok(found.is_some())
+}
+
+/// Appends already-embedded records to a chunk table, creating it on first use with the model
+/// recorded in its metadata.
+pub async fn write_records(lancedb: &Connection, table: &str, model_code: &str, dim: i32, records: Vec<SearchRecord>) -> Result<()> {
+    if records.is_empty() {
+        return Ok(());
+    }
+
+    let schema = record_schema(model_code, dim);
+    let mut keys = Vec::with_capacity(records.len());
+    let mut kinds = Vec::with_capacity(records.len());
+    let mut start_lines = Vec::with_capacity(records.len());
+    let mut end_lines = Vec::with_capacity(records.len());
+    let mut contents = Vec::with_capacity(records.len());
+    let mut vectors = Vec::with_capacity(records.len());
+    for record in records {
+        keys.push(record.key);
+        kinds.push(record.kind);
+        start_lines.push(record.start_line);
+        end_lines.push(record.end_line);
+        contents.push(record.content);
+        vectors.push(Some(record.vector.into_iter().map(Some).collect::<Vec<_>>()));
+    }
+
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(StringArray::from(keys)),
+            Arc::new(StringArray::from(kinds)),
+            Arc::new(UInt32Array::from(start_lines)),
+            Arc::new(UInt32Array::from(end_lines)),
+            Arc::new(StringArray::from(contents)),
+            Arc::new(arrow_array::FixedSizeListArray::from_iter_primitive::<arrow_array::types::Float32Type, _, _>(
+                vectors,
+                dim
+            )),
+        ],
+    )?;
+
+    let table_names: Vec<String> = lancedb.table_names().execute().await?;
+    let batches = RecordBatchIterator::new(vec![Ok(batch)], schema.clone());
+
+    if table_names.iter().any(|name| name == table) {
+        let table = lancedb.open_table(table).execute().await?;
+        table.add(batches).execute().await?;
+    } else {
+        lancedb
+            .create_table(table, batches)
+            .execute()
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Adds records to the SQLite full-text index used by keyword, exact and regex search, and
+/// marks their content as indexed.
+pub async fn write_lexical(sqlite: &SqlitePool, records: &[SearchRecord]) -> Result<()> {
+    let mut tx = sqlite.begin().await?;
+    write_lexical_in(&mut tx, records).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// `write_lexical` within a caller's transaction.
+pub async fn write_lexical_in(tx: &mut sqlx::SqliteConnection, records: &[SearchRecord]) -> Result<()> {
+    for record in records {
+        sqlx::query(&format!("INSERT INTO chunks_fts ({}) VALUES (?, ?, ?, ?, ?)", FTS_COLUMNS))
+            .bind(&record.key)
+            .bind(&record.kind)
+            .bind(record.start_line)
+            .bind(record.end_line)
+            .bind(&record.content)
+            .execute(&mut *tx)
+            .await?;
+        if record.is_content() {
+            sqlx::query("INSERT OR IGNORE INTO indexed_content (content_hash) VALUES (?)")
+                .bind(&record.key)
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads back the stored records (including vectors) with the given keys.
+pub async fn read_records(lancedb: &Connection, table: &str, keys: &HashSet<String>) -> Result<Vec<SearchRecord>> {
+    use futures_util::StreamExt;
+
+    let table_names: Vec<String> = lancedb.table_names().execute().await?;
+    if !table_names.iter().any(|name| name == table) {
+        return Ok(Vec::new());
+    }
+
+    let table = lancedb.open_table(table).execute().await?;
+    let mut results = table.query().execute().await?;
+
+    let mut records = Vec::new();
+    while let Some(batch) = results.next().await {
+        let batch_records = batch_records(&batch?, true)?;
+        records.extend(batch_records.into_iter().filter(|r| keys.contains(&r.key)));
+    }
+
+    Ok(records)
+}
+
+fn batch_records(batch: &RecordBatch, with_vectors: bool) -> Result<Vec<SearchRecord>> {
+    let keys = string_column(batch, "key")?;
+    let kinds = string_column(batch, "kind")?;
+    let start_lines = u32_column(batch, "start_line")?;
+    let end_lines = u32_column(batch, "end_line")?;
+    let contents = string_column(batch, "content")?;
+    let vectors = batch.column_by_name("vector")
+        .context("Missing vector column")?
+        .as_any().downcast_ref::<FixedSizeListArray>()
+        .context("Failed to downcast vector")?;
+
+    let mut records = Vec::with_capacity(batch.num_rows());
+    for i in 0..batch.num_rows() {
+        let vector = if with_vectors {
+            let vector = vectors.value(i);
+            vector.as_any().downcast_ref::<Float32Array>()
+                .context("Failed to downcast vector values")?
+                .values()
+                .to_vec()
+        } else {
+            Vec::new()
+        };
+        records.push(SearchRecord {
+            key: keys.value(i).to_string(),
+            kind: kinds.value(i).to_string(),
+            start_line: start_lines.value(i),
+            end_line: end_lines.value(i),
+            content: contents.value(i).to_string(),
+            vector,
+        });
+    }
+    Ok(records)
+}
+
+fn string_column<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a StringArray> {
+    batch.column_by_name(name)
+        .context(format!("Missing {} column", name))?
+        .as_any().downcast_ref::<StringArray>()
+        .context(format!("Failed to downcast {}", name))
+}
+
+fn u32_column<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a UInt32Array> {
+    batch.column_by_name(name)
+        .context(format!("Missing {} column", name))?
+        .as_any().downcast_ref::<UInt32Array>()
+        .context(format!("Failed to downcast {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glob_to_like, SearchFilters};
+    use sqlx::SqlitePool;
+
+    #[test]
+    fn translates_globs_to_like_patterns() {
+        assert_eq!(glob_to_like("src/**/*.rs"), "src/%/%.rs");
+        assert_eq!(glob_to_like("src/a?.rs"), "src/a_.rs");
+        assert_eq!(glob_to_like("src/my_mod/100%/**"), "src/my\\_mod/100\\%/%");
+        assert_eq!(glob_to_like("src/{auth,db}/**"), "src/%");
+        assert_eq!(glob_to_like("src/*[ab].rs"), "src/%");
+    }
+
+    /// Whether the SQL half of the path filter lets `path` through.
+    async fn like_keeps(glob: &str, path: &str) -> bool {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let filters = SearchFilters { path: Some(glob.to_string()), ..SearchFilters::default() };
+        let mut sql = sqlx::QueryBuilder::new("SELECT COUNT(*) FROM (SELECT ");
+        sql.push_bind(path.to_string()).push(" AS file_path) s WHERE 1 = 1");
+        filters.push_sql(&mut sql);
+        let count: i64 = sql.build_query_scalar().fetch_one(&pool).await.unwrap();
+        count == 1
+    }
+
+    #[tokio::test]
+    async fn brace_and_class_globs_still_match() {
+        for (glob, path) in [("src/{auth,db}/**", "src/auth/jwt.rs"), ("src/[ab]*.rs", "src/a_mod.rs")] {
+            let filters = SearchFilters { path: Some(glob.to_string()), ..SearchFilters::default() };
+            assert!(like_keeps(glob, path).await, "{} should pass {}", path, glob);
+            assert!(filters.keeps(path));
+        }
+        assert!(!like_keeps("src/{auth,db}/**", "lib/auth/jwt.rs").await);
+    }
+
+    #[tokio::test]
+    async fn like_wildcards_in_paths_are_literal() {
+        assert!(like_keeps("src/my_mod/**", "src/my_mod/a.rs").await);
+        assert!(!like_keeps("src/my_mod/**", "src/myXmod/a.rs").await);
+        assert!(!like_keeps("100%/*.rs", "1000/a.rs").await);
+    }
 }
@@ -0,0 +1,154 @@
+//! Finding, installing and loading embedding models without assuming network access.
+//!
+//! Models installed with `stasher model install` live in `<cache>/<model code>/` using the same
+//! file layout as the Hugging Face repository, and are loaded straight from disk. Anything else
+//! is downloaded by fastembed into `<cache>/downloads`, unless the project is configured offline.
+
+use crate::config::Config;
+use anyhow::{Context, Result};
+use fastembed::{
+    EmbeddingModel, InitOptions, InitOptionsUserDefined, TextEmbedding, TokenizerFiles,
+    UserDefinedEmbeddingModel,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const TOKENIZER_FILES: [&str; 4] = [
+    "tokenizer.json",
+    "config.json",
+    "special_tokens_map.json",
+    "tokenizer_config.json",
+];
+
+pub fn parse_model(model_code: &str) -> Result<EmbeddingModel> {
+    EmbeddingModel::from_str(model_code).map_err(anyhow::Error::msg)
+}
+
+/// Where `stasher model install` puts a model.
+pub fn install_dir(cache_dir: &Path, model_code: &str) -> PathBuf {
+    cache_dir.join(model_code.replace('/', "--"))
+}
+
+/// Loads the model from an install, or downloads it when allowed. The error explains what to do
+/// when neither works.
+pub fn load(config: &Config, model_code: &str) -> Result<TextEmbedding> {
+    let model_name = parse_model(model_code)?;
+    let cache_dir = config.model_cache_dir()?;
+
+    let installed = install_dir(&cache_dir, model_code);
+    if installed.exists() {
+        return load_installed(&installed, &model_name);
+    }
+    if config.offline {
+        anyhow::bail!(
+            "{} is not installed and offline mode is on. Install it with `stasher model install --from <dir|archive>`.",
+            model_code
+        );
+    }
+
+    let mut options = InitOptions::default();
+    options.model_name = model_name;
+    options.cache_dir = cache_dir.join("downloads");
+    options.show_download_progress = true;
+    TextEmbedding::try_new(options).context(format!(
+        "Could not download {}. Without network access, install it with `stasher model install --from <dir|archive>`.",
+        model_code
+    ))
+}
+
+fn load_installed(dir: &Path, model_name: &EmbeddingModel) -> Result<TextEmbedding> {
+    let info = TextEmbedding::get_model_info(model_name)?;
+    let read = |name: &str| {
+        fs::read(dir.join(name)).context(format!("Installed model is missing {}", name))
+    };
+
+    let tokenizer_files = TokenizerFiles {
+        tokenizer_file: read(TOKENIZER_FILES[0])?,
+        config_file: read(TOKENIZER_FILES[1])?,
+        special_tokens_map_file: read(TOKENIZER_FILES[2])?,
+        tokenizer_config_file: read(TOKENIZER_FILES[3])?,
+    };
+    let mut model = UserDefinedEmbeddingModel::new(read(&info.model_file)?, tokenizer_files)
+        .with_quantization(TextEmbedding::get_quantization_mode(model_name));
+    if let Some(pooling) = TextEmbedding::get_default_pooling_method(model_name) {
+        model = model.with_pooling(pooling);
+    }
+    model.output_key = info.output_key.clone();
+    for file in &info.additional_files {
+        model = model.with_external_initializer(file.clone(), read(file)?);
+    }
+
+    TextEmbedding::try_new_from_user_defined(model, InitOptionsUserDefined::new())
+}
+
+/// Copies a model's files from a directory or a `.tar`/`.tar.gz` archive into the cache, checking
+/// that everything the model needs is there first. Returns the install directory.
+pub fn install(source: &Path, model_code: &str, cache_dir: &Path) -> Result<PathBuf> {
+    let model_name = parse_model(model_code)?;
+    let info = TextEmbedding::get_model_info(&model_name)?;
+    let mut required: Vec<&str> = TOKENIZER_FILES.to_vec();
+    required.push(&info.model_file);
+    required.extend(info.additional_files.iter().map(String::as_str));
+
+    fs::create_dir_all(cache_dir)?;
+    let staging = cache_dir.join(format!(".install-{}", uuid::Uuid::new_v4()));
+    let result = (|| {
+        let root = if source.is_dir() {
+            source.to_path_buf()
+        } else {
+            unpack(source, &staging)?;
+            find_model_root(&staging, TOKENIZER_FILES[0])
+                .context("Archive does not contain tokenizer.json")?
+        };
+
+        let target = install_dir(cache_dir, model_code);
+        let partial = staging.join("model");
+        for name in &required {
+            let from = root.join(name);
+            if !from.exists() {
+                anyhow::bail!("{} is missing {} (needed by {})", source.display(), name, model_code);
+            }
+            let to = partial.join(name);
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&from, &to).context(format!("Failed to copy {}", name))?;
+        }
+
+        // Swap in the complete install so a failed copy never leaves a half-installed model
+        if target.exists() {
+            fs::remove_dir_all(&target)?;
+        }
+        fs::rename(&partial, &target)?;
+        Ok(target)
+    })();
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+fn unpack(archive: &Path, into: &Path) -> Result<()> {
+    let file = fs::File::open(archive).context(format!("Failed to open {}", archive.display()))?;
+    let name = archive.to_string_lossy();
+    fs::create_dir_all(into)?;
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        tar::Archive::new(flate2::read::GzDecoder::new(file)).unpack(into)?;
+    } else if name.ends_with(".tar") {
+        tar::Archive::new(file).unpack(into)?;
+    } else {
+        anyhow::bail!("Unsupported model archive {} (expected a directory, .tar or .tar.gz)", archive.display());
+    }
+    Ok(())
+}
+
+/// Archives often wrap the files in a top-level folder; find the directory holding `marker`.
+fn find_model_root(dir: &Path, marker: &str) -> Option<PathBuf> {
+    if dir.join(marker).exists() {
+        return Some(dir.to_path_buf());
+    }
+    fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .find_map(|entry| find_model_root(&entry.path(), marker))
+}
@@ -6,12 +6,14 @@ use axum::{
 use tower_http::cors::{Any, CorsLayer};
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use serde::{Deserialize, Serialize};
-use anyhow::{Result, Context};
+use anyhow::Result;
 use crate::hub::{StasherHub, ProjectInfo};
 use crate::db::Database;
-use crate::history::{HistoryManager, SnapshotSummary};
+use crate::history::{HistoryManager, Note, NoteTarget, SnapshotSummary};
+use crate::search::{Occurrence, Prefer, Ranking, SearchFilters, SearchMode};
+use crate::search::global::{self, GlobalQuery, ProjectHit};
 
 #[derive(Clone)]
 pub struct AppState {
@@ -22,13 +24,44 @@ pub struct AppState {
 struct SearchParams {
     q: String,
     global: Option<bool>,
+    path: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    session: Option<String>,
+    branch: Option<String>,
+    lang: Option<String>,
+    /// Half-life in days for favouring recent code.
+    decay: Option<f64>,
+    /// `"first"` to show where code first appeared; the latest version otherwise.
+    prefer: Option<String>,
 }
 
 #[derive(Serialize)]
 struct SearchResultUI {
     project: String,
     file_path: String,
+    kind: String,
+    start_line: u32,
+    end_line: u32,
     content: String,
+    snapshot_id: String,
+    timestamp: i64,
+    score: f64,
+    distance: Option<f32>,
+    occurrences: Vec<Occurrence>,
+}
+
+/// A project the search couldn't cover, and why.
+#[derive(Serialize)]
+struct ProjectFailureUI {
+    project: String,
+    reason: String,
+}
+
+#[derive(Serialize)]
+struct SearchResponseUI {
+    results: Vec<SearchResultUI>,
+    failures: Vec<ProjectFailureUI>,
 }
 
 pub async fn start_server() -> Result<()> {
@@ -43,6 +76,7 @@ pub async fn start_server() -> Result<()> {
     let app = Router::new()
         .route("/api/projects", get(list_projects))
         .route("/api/snapshots", get(list_snapshots))
+        .route("/api/notes", get(list_notes))
         .route("/api/search", post(search))
         .layer(cors)
         .with_state(state);
@@ -90,35 +124,87 @@ async fn list_snapshots(
     Ok(Json(snapshots))
 }
 
+/// Notes attached to any snapshot in a file's history.
+async fn list_notes(
+    State(_state): State<AppState>,
+    Query(params): Query<PathParams>,
+) -> Result<Json<Vec<Note>>, (StatusCode, String)> {
+    let base_path = PathBuf::from(&params.project_path);
+    if !base_path.exists() {
+        return Err((StatusCode::NOT_FOUND, "Project path not found".into()));
+    }
+
+    let db = Database::init(&base_path).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let history = HistoryManager::new(Arc::new(db), base_path).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let snapshots = history.list_snapshots(&params.file_path).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let ids: Vec<String> = snapshots.into_iter().map(|s| s.id).collect();
+    let notes = history.notes_for(NoteTarget::Snapshot, &ids).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(notes.into_values().flatten().collect()))
+}
+
 async fn search(
     State(state): State<AppState>,
     Json(params): Json<SearchParams>,
-) -> Result<Json<Vec<SearchResultUI>>, (StatusCode, String)> {
+) -> Result<Json<SearchResponseUI>, (StatusCode, String)> {
+    let parse_time = |spec: &Option<String>| {
+        spec.as_deref()
+            .map(crate::history::parse_time_spec)
+            .transpose()
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+    };
+    let filters = SearchFilters {
+        path: params.path.clone(),
+        since: parse_time(&params.since)?,
+        until: parse_time(&params.until)?,
+        session: params.session.clone(),
+        branch: params.branch.clone(),
+        lang: params.lang.clone(),
+    };
+    filters.validate().map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let ranking = Ranking {
+        half_life_days: params.decay.filter(|days| *days > 0.0),
+        prefer: match params.prefer.as_deref() {
+            Some("first") => Prefer::First,
+            _ => Prefer::Latest,
+        },
+    };
+
     let mut all_results = Vec::new();
+    let mut failures = Vec::new();
 
     if params.global.unwrap_or(true) {
         let projects = state.hub.list_projects().await
             .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-        
-        for project in projects {
-            let project_path = PathBuf::from(&project.path);
-            if !project_path.exists() { continue; }
-
-            if let Ok(db) = Database::init(&project_path).await {
-                if let Ok(search_engine) = crate::search::SearchEngine::new(db.lancedb.clone()).await {
-                    if let Ok(results) = search_engine.search(params.q.clone(), 3).await {
-                        for res in results {
-                            all_results.push(SearchResultUI {
-                                project: project.name.clone(),
-                                file_path: res.file_path,
-                                content: res.content,
-                            });
-                        }
-                    }
-                }
-            }
+        let query = GlobalQuery::Search { query: params.q.clone(), mode: SearchMode::Hybrid };
+        let results = global::search_projects(projects, query, 10, &filters, &ranking, global::DEFAULT_TIMEOUT).await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        failures = results.failures.into_iter()
+            .map(|(project, reason)| ProjectFailureUI { project, reason })
+            .collect();
+
+        for ProjectHit { project, hit: res } in results.hits {
+            let shown = res.shown();
+            all_results.push(SearchResultUI {
+                project,
+                file_path: shown.file_path.clone(),
+                snapshot_id: shown.snapshot_id.clone(),
+                timestamp: shown.timestamp,
+                kind: res.record.kind,
+                start_line: res.record.start_line,
+                end_line: res.record.end_line,
+                content: res.record.content,
+                score: res.score,
+                distance: res.distance,
+                occurrences: res.occurrences,
+            });
         }
     }
 
-    Ok(Json(all_results))
+    Ok(Json(SearchResponseUI { results: all_results, failures }))
 }
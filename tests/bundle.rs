@@ -0,0 +1,66 @@
+//! Moves history between projects through a bundle file.
+
+mod common;
+
+use common::Project;
+use stasher::bundle::{self, BundleFilter};
+use stasher::db::Database;
+
+#[tokio::test]
+async fn round_trips_filtered_history() {
+    let source = Project::new().await;
+    source.save("src/lib.rs", "fn one() {}\n").await;
+    source.save("src/lib.rs", "fn one() {}\nfn two() {}\n").await;
+    source.save("docs/notes.md", "# Notes\n").await;
+
+    let source_db = Database::init(&source.root).await.unwrap();
+    let filter = BundleFilter { path_glob: Some("src/**".to_string()), ..BundleFilter::default() };
+    let mut created = bundle::create_bundle(&source_db, &source.root, &filter).await.unwrap();
+    assert_eq!(created.snapshots.len(), 2);
+    assert!(created.snapshots.iter().all(|s| s.file_path == "src/lib.rs"));
+
+    // Objects travel as raw bytes, text or not
+    let binary = vec![0xff, 0xfe, 0x00, 0x01];
+    created.objects.insert(blake3::hash(&binary).to_hex().to_string(), binary);
+    let file = source.root.join("history.bundle");
+    bundle::write_bundle(&created, &file).unwrap();
+    let read = bundle::read_bundle(&file).unwrap();
+    assert_eq!(read.objects, created.objects);
+
+    let target = Project::new().await;
+    let target_db = Database::init(&target.root).await.unwrap();
+    let stats = bundle::import_bundle(&target_db, &target.root, read).await.unwrap();
+    assert_eq!((stats.sessions, stats.snapshots, stats.objects), (1, 2, 3));
+    let imported = target.history
+        .list_snapshots(&target.root.join("src/lib.rs").to_string_lossy())
+        .await
+        .unwrap();
+    assert_eq!(imported.len(), 2);
+
+    // Importing the same bundle again changes nothing
+    let again = bundle::import_bundle(&target_db, &target.root, bundle::read_bundle(&file).unwrap()).await.unwrap();
+    assert_eq!((again.sessions, again.snapshots, again.objects, again.keyword_only), (0, 0, 0, 0));
+}
+
+#[tokio::test]
+async fn refuses_corrupt_objects() {
+    let source = Project::new().await;
+    source.save("src/lib.rs", "fn one() {}\n").await;
+
+    let source_db = Database::init(&source.root).await.unwrap();
+    let mut created = bundle::create_bundle(&source_db, &source.root, &BundleFilter::default()).await.unwrap();
+    for content in created.objects.values_mut() {
+        content.push(b'!');
+    }
+    let file = source.root.join("history.bundle");
+    bundle::write_bundle(&created, &file).unwrap();
+
+    let target = Project::new().await;
+    let target_db = Database::init(&target.root).await.unwrap();
+    let Err(err) = bundle::import_bundle(&target_db, &target.root, bundle::read_bundle(&file).unwrap()).await else {
+        panic!("a corrupt object was imported");
+    };
+    assert!(err.to_string().contains("corrupt"), "{:#}", err);
+    let snapshots: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM snapshots").fetch_one(&target_db.sqlite).await.unwrap();
+    assert_eq!(snapshots, 0);
+}
@@ -0,0 +1,47 @@
+//! A throwaway project shared by the integration tests.
+
+use stasher::config::{Config, EmbeddingBackend};
+use stasher::db::Database;
+use stasher::history::HistoryManager;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A project directory with the hash embedder standing in for a model, removed when dropped.
+pub struct Project {
+    pub root: PathBuf,
+    pub history: HistoryManager,
+}
+
+impl Project {
+    pub async fn new() -> Self {
+        let root = std::env::temp_dir().join(format!("stasher-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+
+        let db = Database::init(&root).await.unwrap();
+        let config = Config {
+            embedding_backend: EmbeddingBackend::Hash,
+            embedding_dim: Some(64),
+            ..Config::default()
+        };
+        config.save(&root).unwrap();
+        let history = HistoryManager::new(Arc::new(db), root.clone()).await.unwrap();
+        Self { root, history }
+    }
+
+    /// Writes `content` to `rel_path` and records it the way the daemon would.
+    pub async fn save(&self, rel_path: &str, content: &str) -> PathBuf {
+        let path = self.root.join(rel_path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        self.history.record_change(path.clone()).await.unwrap();
+        path
+    }
+}
+
+impl Drop for Project {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
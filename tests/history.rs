@@ -0,0 +1,113 @@
+//! Records and searches history end to end, with the hash embedder standing in for a model.
+
+mod common;
+
+use common::Project;
+use stasher::search::{Ranking, SearchFilters, SearchHit, SearchMode};
+use std::fs;
+use std::path::Path;
+
+async fn ask(project: &Project, query: &str, mode: SearchMode, filters: &SearchFilters) -> Vec<SearchHit> {
+    project.history
+        .search_engine()
+        .search(query.to_string(), 5, mode, filters, &Ranking::default())
+        .await
+        .unwrap()
+}
+
+fn path_of(path: &Path) -> String {
+    path.to_string_lossy().to_string()
+}
+
+#[tokio::test]
+async fn record_change_stores_each_version_once() {
+    let project = Project::new().await;
+    let file = project.save("src/lib.rs", "fn one() {}\n").await;
+    project.history.record_change(file.clone()).await.unwrap();
+    project.save("src/lib.rs", "fn one() {}\nfn two() {}\n").await;
+
+    let snapshots = project.history.list_snapshots(&path_of(&file)).await.unwrap();
+    assert_eq!(snapshots.len(), 2);
+    assert!(snapshots.iter().all(|s| s.file_path == "src/lib.rs"));
+    assert!(snapshots.iter().any(|s| s.lines_added == 1 && s.lines_removed == 0));
+}
+
+#[tokio::test]
+async fn ask_finds_recorded_code() {
+    let project = Project::new().await;
+    project.save("src/auth.rs", "pub fn parse_jwt_claims(token: &str) -> Claims {\n    decode(token)\n}\n").await;
+    project.save("src/db.rs", "pub fn open_pool(url: &str) -> Pool {\n    Pool::connect(url)\n}\n").await;
+
+    let hits = ask(&project, "parse_jwt_claims", SearchMode::Hybrid, &SearchFilters::default()).await;
+    assert!(!hits.is_empty());
+    assert_eq!(hits[0].shown().file_path, "src/auth.rs");
+    assert!(hits[0].distance.is_some(), "the hash embedder should have produced vectors");
+}
+
+#[tokio::test]
+async fn ask_finds_deleted_code() {
+    let project = Project::new().await;
+    project.save("src/token.rs", "fn keep() {}\nfn legacy_token_refresh() {}\n").await;
+    project.save("src/token.rs", "fn keep() {}\n").await;
+
+    let hits = ask(&project, "legacy_token_refresh", SearchMode::Exact, &SearchFilters::default()).await;
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].shown().file_path, "src/token.rs");
+    // The old version's content and the removal fold into one hit for the file
+    assert_eq!(hits[0].folded, 1);
+}
+
+#[tokio::test]
+async fn filters_apply_before_the_limit() {
+    let project = Project::new().await;
+    for n in 0..12 {
+        project.save(&format!("src/m{}.rs", n), &format!("fn shared_helper_{}() {{ shared_helper() }}\n", n)).await;
+    }
+    project.save("tools/run.py", "def main():\n    shared_helper()\n").await;
+
+    let filters = SearchFilters { lang: Some("python".to_string()), ..SearchFilters::default() };
+    for mode in [SearchMode::Hybrid, SearchMode::Exact] {
+        let hits = ask(&project, "shared_helper", mode, &filters).await;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].shown().file_path, "tools/run.py");
+        // The vector query only considered the filtered chunks, so it found this one too
+        assert_eq!(hits[0].distance.is_some(), mode == SearchMode::Hybrid);
+    }
+}
+
+#[tokio::test]
+async fn deleted_and_moved_files_leave_later_states() {
+    let project = Project::new().await;
+    let kept = project.save("src/kept.rs", "fn kept() {}\n").await;
+    let old = project.save("src/old.rs", "fn moved() {}\n").await;
+    let gone = project.save("src/gone.rs", "fn gone() {}\n").await;
+    let before = chrono::Utc::now().timestamp_millis();
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+    fs::remove_file(&gone).unwrap();
+    project.history.record_deletion(&gone).await.unwrap();
+    let new = project.root.join("src/new.rs");
+    fs::rename(&old, &new).unwrap();
+    project.history.record_deletion(&old).await.unwrap();
+    project.history.record_change(new).await.unwrap();
+    // Recording the same deletion twice is harmless
+    project.history.record_deletion(&gone).await.unwrap();
+
+    let paths = |entries: Vec<stasher::history::TreeEntry>| entries.into_iter().map(|e| e.file_path).collect::<Vec<_>>();
+    let now = chrono::Utc::now().timestamp_millis();
+    assert_eq!(paths(project.history.tree_at(now).await.unwrap()), ["src/kept.rs", "src/new.rs"]);
+    assert_eq!(paths(project.history.tree_at(before).await.unwrap()), ["src/gone.rs", "src/kept.rs", "src/old.rs"]);
+    assert!(kept.exists());
+}
+
+#[tokio::test]
+async fn read_only_projects_refuse_writes() {
+    let project = Project::new().await;
+    project.save("src/lib.rs", "fn one() {}\n").await;
+
+    let db = stasher::db::Database::open_read_only(&project.root).await.unwrap();
+    let snapshots: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM snapshots").fetch_one(&db.sqlite).await.unwrap();
+    assert_eq!(snapshots, 1);
+    assert!(sqlx::query("DELETE FROM snapshots").execute(&db.sqlite).await.is_err());
+    assert!(stasher::db::Database::open_read_only(&project.root.join("elsewhere")).await.is_err());
+}
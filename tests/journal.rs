@@ -0,0 +1,126 @@
+//! Restores, checkouts and reverts as transactions, and undoing them.
+
+mod common;
+
+use common::Project;
+use std::fs;
+use std::time::Duration;
+
+/// A moment after everything saved so far, as a checkout reference.
+async fn moment() -> String {
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    let now = chrono::Utc::now().timestamp_millis();
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    now.to_string()
+}
+
+async fn snapshot_count(project: &Project, rel_path: &str) -> usize {
+    project.history.list_snapshots(&project.root.join(rel_path).to_string_lossy()).await.unwrap().len()
+}
+
+#[tokio::test]
+async fn refuses_files_changed_since_planning() {
+    let project = Project::new().await;
+    let file = project.save("src/lib.rs", "fn one() {}\n").await;
+    project.save("src/lib.rs", "fn two() {}\n").await;
+
+    let plan = project.history.plan_restore(&file.to_string_lossy(), None).await.unwrap();
+    assert!(plan.files.is_empty(), "the file already holds its latest version");
+    fs::write(&file, "fn three() {}\n").unwrap();
+    let plan = project.history.plan_restore(&file.to_string_lossy(), None).await.unwrap();
+    fs::write(&file, "fn four() {}\n").unwrap();
+
+    let err = project.history.apply_restore(&plan).await.unwrap_err();
+    assert!(err.to_string().contains("changed after the restore was planned"), "{:#}", err);
+    assert_eq!(fs::read_to_string(&file).unwrap(), "fn four() {}\n");
+}
+
+#[tokio::test]
+async fn failed_write_puts_back_earlier_files() {
+    let project = Project::new().await;
+    let first = project.save("a.rs", "fn a() {}\n").await;
+    let second = project.save("z/b.rs", "fn b() {}\n").await;
+    let before = moment().await;
+    project.save("a.rs", "fn a2() {}\n").await;
+
+    // A plain file where z/ should be, so writing z/b.rs fails after a.rs was written
+    fs::remove_dir_all(second.parent().unwrap()).unwrap();
+    fs::write(project.root.join("z"), "not a directory\n").unwrap();
+
+    let plan = project.history.plan_checkout(&before, &[]).await.unwrap();
+    assert_eq!(plan.files.len(), 2);
+    let err = project.history.apply_restore(&plan).await.unwrap_err();
+    assert!(format!("{:#}", err).contains("All files were put back"), "{:#}", err);
+    assert_eq!(fs::read_to_string(&first).unwrap(), "fn a2() {}\n");
+    // A rolled-back checkout leaves nothing to undo
+    assert!(project.history.undo_last_restore().await.is_err());
+}
+
+#[tokio::test]
+async fn undo_removes_files_the_checkout_created() {
+    let project = Project::new().await;
+    let kept = project.save("src/kept.rs", "fn kept() {}\n").await;
+    let created = project.save("src/created.rs", "fn created() {}\n").await;
+    let before = moment().await;
+    fs::remove_file(&created).unwrap();
+
+    let plan = project.history.plan_checkout(&before, &[]).await.unwrap();
+    assert_eq!((plan.files.len(), plan.unchanged), (1, 1));
+    project.history.apply_restore(&plan).await.unwrap();
+    assert_eq!(fs::read_to_string(&created).unwrap(), "fn created() {}\n");
+
+    let outcome = project.history.undo_last_restore().await.unwrap();
+    assert_eq!((outcome.command.as_str(), outcome.files, outcome.interrupted), ("checkout", 1, false));
+    assert!(!created.exists());
+    assert!(kept.exists());
+}
+
+#[tokio::test]
+async fn undo_refuses_after_the_file_was_edited() {
+    let project = Project::new().await;
+    let file = project.save("src/lib.rs", "fn one() {}\n").await;
+    fs::write(&file, "fn two() {}\n").unwrap();
+
+    let plan = project.history.plan_restore(&file.to_string_lossy(), None).await.unwrap();
+    project.history.apply_restore(&plan).await.unwrap();
+    fs::write(&file, "fn three() {}\n").unwrap();
+
+    let Err(err) = project.history.undo_last_restore().await else {
+        panic!("undo discarded an edit");
+    };
+    assert!(err.to_string().contains("edited after the restore"), "{:#}", err);
+    assert_eq!(fs::read_to_string(&file).unwrap(), "fn three() {}\n");
+}
+
+#[tokio::test]
+async fn restores_over_files_that_are_not_text() {
+    let project = Project::new().await;
+    let file = project.save("data.txt", "text\n").await;
+    let binary = vec![0xff, 0xfe, 0x00, 0x01];
+    fs::write(&file, &binary).unwrap();
+
+    let plan = project.history.plan_restore(&file.to_string_lossy(), None).await.unwrap();
+    project.history.apply_restore(&plan).await.unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "text\n");
+    // Snapshots only hold text, so the overwritten bytes are kept for undo alone
+    assert_eq!(snapshot_count(&project, "data.txt").await, 1);
+
+    project.history.undo_last_restore().await.unwrap();
+    assert_eq!(fs::read(&file).unwrap(), binary);
+}
+
+#[tokio::test]
+async fn undo_reverses_a_revert() {
+    let project = Project::new().await;
+    let file = project.save("src/lib.rs", "fn one() {}\n").await;
+    project.save("src/lib.rs", "fn one() {}\nfn two() {}\n").await;
+    let latest = project.history.list_snapshots(&file.to_string_lossy()).await.unwrap().remove(0);
+
+    let outcome = project.history.revert(&latest.id, None).await.unwrap();
+    assert!(!outcome.unchanged);
+    assert_eq!(fs::read_to_string(&file).unwrap(), "fn one() {}\n");
+
+    let undone = project.history.undo_last_restore().await.unwrap();
+    assert_eq!(undone.command, "revert");
+    assert_eq!(fs::read_to_string(&file).unwrap(), "fn one() {}\nfn two() {}\n");
+}
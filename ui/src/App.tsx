@@ -36,11 +36,20 @@ interface Snapshot {
   content_hash: string;
 }
 
+interface Note {
+  id: string;
+  target_kind: string;
+  target_id: string;
+  body: string;
+  created_at: number;
+}
+
 // --- Main App ---
 export default function StasherDashboard() {
   const [projects, setProjects] = useState<Project[]>([]);
   const [activeProject, setActiveProject] = useState<Project | null>(null);
   const [snapshots, setSnapshots] = useState<Snapshot[]>([]);
+  const [notes, setNotes] = useState<Note[]>([]);
   const [searchQuery, setSearchQuery] = useState('');
 
   useEffect(() => {
@@ -70,9 +79,21 @@ export default function StasherDashboard() {
     }
   };
 
+  const fetchNotes = async (project: Project, file = "src/main.rs") => {
+    try {
+      const res = await axios.get(`http://localhost:3000/api/notes`, {
+        params: { project_path: project.path, file_path: file }
+      });
+      setNotes(res.data);
+    } catch (err) {
+      console.error("Failed to fetch notes", err);
+    }
+  };
+
   useEffect(() => {
     if (activeProject) {
       fetchSnapshots(activeProject);
+      fetchNotes(activeProject);
     }
   }, [activeProject]);
 
@@ -207,6 +228,12 @@ export default function StasherDashboard() {
                         <p className="text-xs text-slate-500 leading-relaxed">Recorded during development session on file save trigger.</p>
                       </div>
 
+                      {notes.filter((note) => note.target_id === snap.id).map((note) => (
+                        <div key={note.id} className="mb-4 p-3 rounded-xl bg-brand-coral/5 border border-brand-coral/10">
+                          <p className="text-xs text-slate-300 leading-relaxed whitespace-pre-wrap">{note.body}</p>
+                        </div>
+                      ))}
+
                       <div className="bg-[#0b0f19] rounded-2xl p-4 border border-slate-800/50">
                         <pre className="text-[11px] leading-6 font-mono text-slate-400 overflow-x-auto whitespace-pre">
                           {`function update_logic(delta) {